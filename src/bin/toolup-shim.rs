@@ -1,7 +1,10 @@
 use anyhow::Result as AnyResult;
 use std::env;
 use std::path::Path;
-use toolup::prelude::{exec, get_current_state, GlobalFolders};
+use toolup::prelude::{
+    exec, get_current_state, resolve_binary, spawn_background_update, GlobalFolders,
+    TOOLUP_VERSION_OVERRIDE,
+};
 
 #[tokio::main]
 async fn main() -> AnyResult<()> {
@@ -26,9 +29,21 @@ async fn main() -> AnyResult<()> {
     let global_state = global_folder.global_state_file();
 
     let container = get_current_state(&global_state).await?;
-    let path = container.current_state.get_current_binary_path(&command)?;
-
-    exec(path, args);
+    let version_override = env::var(TOOLUP_VERSION_OVERRIDE).ok();
+    let resolved = resolve_binary(
+        &container.current_state,
+        &command,
+        version_override.as_deref(),
+        &env::current_dir()?,
+    )?;
+    let path = resolved.binary.path_to_exec.clone();
+    let (package_env, mut package_args) = resolved.exec_options(&container.current_state)?;
+    package_args.extend(args);
+
+    // Updating is best effort, it must never stop the command from running.
+    spawn_background_update(&container, &command, &global_folder).ok();
+
+    exec(path, package_args, package_env);
 
     Ok(())
 }
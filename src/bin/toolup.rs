@@ -39,6 +39,11 @@ async fn run_command(opts: Opts, global_folder: &GlobalFolders) -> Result<(), Co
     let result = match opts.sub_command {
         SubCommand::Package(args) => handle_package(args, global_folder).await?,
         SubCommand::Exec(args) => handle_exec(args, global_folder).await?,
+        SubCommand::Use(args) => handle_use(args, global_folder).await?,
+        SubCommand::Gc(args) => handle_gc(args, global_folder).await?,
+        SubCommand::Which(args) => handle_which(args, global_folder).await?,
+        SubCommand::Conflicts(args) => handle_conflicts(args, global_folder).await?,
+        SubCommand::Verify(args) => handle_verify(args, global_folder).await?,
         SubCommand::Remote(args) => handle_remote(args, global_folder).await?,
         SubCommand::Config(args) => handle_config(args, global_folder).await?,
         SubCommand::Version => print_version(),
@@ -26,6 +26,23 @@ pub enum SubCommand {
     /// Exec one of the installed packages
     Exec(ExecSubCommand),
 
+    /// Make an already installed version of a package the current one
+    Use(UseSubCommand),
+
+    /// Remove old package versions and leftover files from failed installs
+    Gc(GcSubCommand),
+
+    /// Show which binary will run for a command, and why
+    #[clap(alias = "resolve")]
+    Which(WhichSubCommand),
+
+    /// List binaries that are provided by more than one package
+    Conflicts(ConflictsSubCommand),
+
+    /// Check installed packages for files that were changed, removed, or added since install
+    #[clap(alias = "doctor")]
+    Verify(VerifySubCommand),
+
     /// Get config details
     #[clap(subcommand)]
     Config(ConfigSubCommand),
@@ -47,6 +64,8 @@ pub struct GetPathSubCommand {}
 #[derive(Parser, Debug)]
 pub struct ExecSubCommand {
     /// Use a specific version of the binary, not the current one.
+    ///
+    /// May be a semver range, like `^1.2`, to use the highest matching installed version.
     #[clap(long, env = "TOOLUP_VERSION_OVERRIDE")]
     pub version: Option<String>,
     /// Name of the command to execute
@@ -55,6 +74,57 @@ pub struct ExecSubCommand {
     pub args: Vec<String>,
 }
 
+#[derive(Parser, Debug)]
+pub struct ConflictsSubCommand {}
+
+#[derive(Parser, Debug)]
+pub struct VerifySubCommand {
+    /// Only check this package, in the form `<package>[@<version>]`. Checks every installed package by default.
+    pub package: Option<String>,
+
+    /// Download packages that have problems from their remote again, and reinstall them.
+    #[clap(long)]
+    pub repair: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct WhichSubCommand {
+    /// Name of the binary to resolve, as it would be run through the shim.
+    pub binary: String,
+
+    /// List every installed version that provides the binary, across all packages.
+    #[clap(long)]
+    pub all: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct UseSubCommand {
+    /// The package and version to make current, in the form `<package>@<version>`.
+    ///
+    /// Versions that are not installed are downloaded from the package's remote, when it is an index remote.
+    /// The version may be a semver range, like `~2`, to use the highest matching version.
+    /// A partial version, like `1.2`, selects the highest `1.2.x` version.
+    pub package: String,
+
+    /// Replace binaries that are currently provided by other packages.
+    #[clap(long)]
+    pub take_over: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct GcSubCommand {
+    /// Number of versions, in addition to the current one, to keep for each package.
+    ///
+    /// A `keep-versions` value set on the package's remote takes precedence. When neither
+    /// is set, every version of the package is kept.
+    #[clap(long)]
+    pub keep_versions: Option<usize>,
+
+    /// Print what would be removed, without removing anything.
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
 #[derive(Parser, Debug)]
 #[clap(color = ColorChoice::Always)]
 pub enum PackageSubCommand {
@@ -64,6 +134,16 @@ pub enum PackageSubCommand {
     Archive(ArchiveToolSubCommand),
     /// Install a local package archive
     Install(InstallToolSubCommand),
+    /// List all the installed packages
+    List(ListPackageSubCommand),
+    /// Remove installed versions of a package, and delete them from disk
+    Remove(RemovePackageSubCommand),
+    /// Show the archive definition of an installed package
+    Show(ShowPackageSubCommand),
+    /// Check a package archive's file hashes and signature, without installing it
+    Verify(VerifyPackageSubCommand),
+    /// Generate a key pair for signing packages
+    GenerateKey(GenerateKeySubCommand),
 }
 
 #[derive(Parser, Debug)]
@@ -71,7 +151,7 @@ pub struct ArchiveToolSubCommand {
     /// Location on disk that has the artifact directory ready.
     ///
     /// All files relative to this directory will be packaged up for distribution.
-    /// There is a limit of 128 MiB total uncompressed files.
+    /// The total uncompressed size is limited by `--max-size-mib`.
     #[clap(long)]
     pub target_dir: String,
 
@@ -82,6 +162,28 @@ pub struct ArchiveToolSubCommand {
     /// Directory to write the archive to. The final file will be named `{name}-{version}.tar.gz`.
     #[clap(long)]
     pub archive_dir: String,
+
+    /// Allow a version that isn't semver. These versions are ordered lexically, below every semver version.
+    #[clap(long)]
+    pub allow_non_semver: bool,
+
+    /// The largest total uncompressed size of the files being packaged, in MiB.
+    #[clap(long, default_value = "128")]
+    pub max_size_mib: u64,
+
+    /// The largest any one file being packaged can be, in MiB. By default only the total is limited.
+    #[clap(long)]
+    pub max_file_size_mib: Option<u64>,
+
+    /// Sign the package with the secret key in this file, created by `toolup package generate-key`.
+    #[clap(long)]
+    pub sign_key: Option<String>,
+
+    /// The platform the package is built for, like `linux-x86_64-gnu` or `macos-aarch64`.
+    ///
+    /// Overrides `target` in the config file. When set, the target is added to the archive name.
+    #[clap(long)]
+    pub target: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -104,6 +206,81 @@ pub struct InstallToolSubCommand {
     /// When set, toolup will clearn out the destination directory if it exists.
     #[clap(long)]
     pub overwrite: bool,
+
+    /// Replace binaries that are currently provided by other packages.
+    #[clap(long)]
+    pub take_over: bool,
+
+    /// Only install the package if it's signed by this base64 encoded public key. Can be given more than once.
+    #[clap(long = "trusted-key")]
+    pub trusted_keys: Vec<String>,
+
+    #[clap(flatten)]
+    pub limits: InstallLimitOpts,
+}
+
+#[derive(Parser, Debug)]
+pub struct VerifyPackageSubCommand {
+    /// Location on disk of the package archive to check.
+    pub archive_path: String,
+
+    /// Require the package to be signed by this base64 encoded public key. Can be given more than once.
+    #[clap(long = "trusted-key")]
+    pub trusted_keys: Vec<String>,
+
+    #[clap(flatten)]
+    pub limits: InstallLimitOpts,
+}
+
+/// Limits on what an archive may expand to when it's extracted. Anything not given uses the default.
+#[derive(Parser, Debug)]
+pub struct InstallLimitOpts {
+    /// The most files and directories an archive may contain. Defaults to 100000.
+    #[clap(long)]
+    pub max_entries: Option<u64>,
+
+    /// The largest total uncompressed size of an archive, in MiB. Defaults to 16 GiB.
+    #[clap(long)]
+    pub max_total_mib: Option<u64>,
+
+    /// The most an archive may expand by, as its uncompressed size over its compressed size. Defaults to 100.
+    #[clap(long)]
+    pub max_compression_ratio: Option<u64>,
+}
+
+#[derive(Parser, Debug)]
+pub struct GenerateKeySubCommand {
+    /// File to write the secret key to. The public key is printed.
+    #[clap(long)]
+    pub output: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct ListPackageSubCommand {
+    /// Print the packages as JSON instead of a table.
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct RemovePackageSubCommand {
+    /// The package to remove, in the form `<package>[@<version>]`.
+    ///
+    /// When no version is given, every installed version of the package is removed.
+    /// When the current version is removed, the highest remaining version becomes current.
+    pub package: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct ShowPackageSubCommand {
+    /// The package to show, in the form `<package>[@<version>]`.
+    ///
+    /// When no version is given, the current version is shown.
+    pub package: String,
+
+    /// Print the definition as JSON instead of a table.
+    #[clap(long)]
+    pub json: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -111,7 +288,7 @@ pub struct InstallToolSubCommand {
 pub enum RemoteSubCommand {
     /// Add a remote tool configuration
     #[clap(subcommand)]
-    Add(AddRemoteSubCommand),
+    Add(Box<AddRemoteSubCommand>),
     /// Delete a remote tool configuration
     Delete(DeleteRemoteSubCommand),
     /// List all the installed remotes
@@ -125,6 +302,20 @@ pub struct UpdateRemoteSubCommand {
     /// When specified, only the remote matching the name provided will be updated.
     #[clap(long)]
     pub only: Option<String>,
+
+    /// Check remotes for updates, even when they were checked within their update period.
+    #[clap(long)]
+    pub force: bool,
+
+    /// Replace binaries that are currently provided by other packages.
+    #[clap(long)]
+    pub take_over: bool,
+
+    /// Download every remote, even when it's up to date, and print the sha256 of each archive.
+    ///
+    /// Nothing is installed, and archives are not checked against the remote's `--sha256`.
+    #[clap(long)]
+    pub print_checksums: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -136,7 +327,7 @@ pub struct DeleteRemoteSubCommand {
     #[clap(long)]
     pub name: String,
 
-    /// When set, the related package will also be removed.
+    /// When set, the related packages will also be removed, and deleted from disk.
     #[clap(long)]
     pub cascade: bool,
 }
@@ -148,6 +339,12 @@ pub enum AddRemoteSubCommand {
     Local(AddRemoteLocalSubCommand),
     /// Create a remote backed by an S3 bucket
     S3(AddRemoteS3SubCommand),
+    /// Create a remote backed by a HTTP(S) server
+    Http(AddRemoteHttpSubCommand),
+    /// Create a remote backed by a git repo
+    Git(AddRemoteGitSubCommand),
+    /// Create a remote that follows a channel or version range of an `index.json`
+    Index(AddRemoteIndexSubCommand),
 }
 
 #[derive(Parser, Debug)]
@@ -160,6 +357,9 @@ pub struct AddRemoteLocalSubCommand {
     /// The Location on disk to install the package from.
     #[clap(long)]
     pub path: String,
+
+    #[clap(flatten)]
+    pub options: AddRemoteOptions,
 }
 
 #[derive(Parser, Debug)]
@@ -173,12 +373,145 @@ pub struct AddRemoteS3SubCommand {
     #[clap(long)]
     pub url: String,
 
+    /// A URL to use instead of `--url` on one platform, written as `<target>=<url>`, like
+    /// `linux-x86_64=https://example.com/tool-linux.tar.gz`. May be repeated.
+    #[clap(long = "target-url")]
+    pub target_urls: Vec<String>,
+
     #[clap(long, arg_enum, default_value("anonymous"))]
     pub auth: S3AuthType,
 
     #[clap(long, required_if_eq("auth", "host"))]
     /// Location of script, that will export environment variables to auth with S3
     pub auth_script: Option<String>,
+
+    #[clap(flatten)]
+    pub options: AddRemoteOptions,
+}
+
+#[derive(Parser, Debug)]
+pub struct AddRemoteHttpSubCommand {
+    /// Name for the remove. This name must be unique between remote packages.
+    /// Usually this should be the name of the package.
+    #[clap(long)]
+    pub name: String,
+
+    /// The URL to download the package from.
+    #[clap(long)]
+    pub url: String,
+
+    /// A URL to use instead of `--url` on one platform, written as `<target>=<url>`, like
+    /// `linux-x86_64=https://example.com/tool-linux.tar.gz`. May be repeated.
+    #[clap(long = "target-url")]
+    pub target_urls: Vec<String>,
+
+    #[clap(long, arg_enum, default_value("none"))]
+    pub auth: HttpAuthType,
+
+    #[clap(long, required_if_eq_any(&[("auth", "bearer"), ("auth", "basic")]))]
+    /// Location of script, that will export `TOOLUP_HTTP_TOKEN` for bearer auth,
+    /// or `TOOLUP_HTTP_USERNAME` and `TOOLUP_HTTP_PASSWORD` for basic auth.
+    pub auth_script: Option<String>,
+
+    #[clap(flatten)]
+    pub options: AddRemoteOptions,
+}
+
+#[derive(Parser, Debug)]
+#[clap(group = ArgGroup::new("artifact").required(true))]
+pub struct AddRemoteGitSubCommand {
+    /// Name for the remove. This name must be unique between remote packages.
+    /// Usually this should be the name of the package.
+    #[clap(long)]
+    pub name: String,
+
+    /// The URL to clone the repo from. Credentials come from the ssh-agent or git credential helpers.
+    #[clap(long)]
+    pub url: String,
+
+    /// Branch, tag, or full ref to install from. Defaults to the repo's HEAD.
+    #[clap(long)]
+    pub reference: Option<String>,
+
+    /// Path, inside the repo, to a `package.toml` that will be archived on every update.
+    #[clap(long, group = "artifact")]
+    pub config: Option<String>,
+
+    /// Path, inside the repo, to the directory to archive. Defaults to the directory containing `--config`.
+    #[clap(long, requires = "config")]
+    pub target_dir: Option<String>,
+
+    /// Path, inside the repo, to a pre-built `.tar.gz` package.
+    #[clap(long, group = "artifact")]
+    pub archive_path: Option<String>,
+
+    #[clap(flatten)]
+    pub options: AddRemoteOptions,
+}
+
+#[derive(Parser, Debug)]
+pub struct AddRemoteIndexSubCommand {
+    /// Name for the remove. This name must be unique between remote packages.
+    /// Usually this should be the name of the package.
+    #[clap(long)]
+    pub name: String,
+
+    /// The URL of the `index.json`. May be a `file://` URL.
+    #[clap(long)]
+    pub url: String,
+
+    /// Only install versions tagged with this channel, e.g. `stable`.
+    #[clap(long)]
+    pub channel: Option<String>,
+
+    /// Only install versions matching this semver range, e.g. `^1.2`.
+    /// A partial version, like `1.2`, only matches `1.2.x` versions.
+    #[clap(long)]
+    pub version_req: Option<String>,
+
+    #[clap(long, arg_enum, default_value("none"))]
+    pub auth: HttpAuthType,
+
+    #[clap(long, required_if_eq_any(&[("auth", "bearer"), ("auth", "basic")]))]
+    /// Location of script, that will export `TOOLUP_HTTP_TOKEN` for bearer auth,
+    /// or `TOOLUP_HTTP_USERNAME` and `TOOLUP_HTTP_PASSWORD` for basic auth.
+    pub auth_script: Option<String>,
+
+    #[clap(flatten)]
+    pub options: AddRemoteOptions,
+}
+
+#[derive(ArgEnum, Debug, PartialEq, Clone)]
+pub enum HttpAuthType {
+    None,
+    Bearer,
+    Basic,
+}
+
+#[derive(Parser, Debug)]
+pub struct AddRemoteOptions {
+    /// Number of seconds to wait between checking the remote for updates. Defaults to one day.
+    ///
+    /// `remote update` will skip this remote until the period has passed, unless `--force` is used.
+    #[clap(long)]
+    pub update_period: Option<i64>,
+
+    /// Number of versions, in addition to the current one, to keep when running `toolup gc`.
+    #[clap(long)]
+    pub keep_versions: Option<usize>,
+
+    /// Only install packages signed by this base64 encoded public key. Can be given more than once.
+    #[clap(long = "trusted-key")]
+    pub trusted_keys: Vec<String>,
+
+    /// The sha256 the downloaded archive must have. Use this to freeze a remote at one version.
+    ///
+    /// `remote update --print-checksums` prints the sha256 of what a remote currently serves.
+    #[clap(long)]
+    pub sha256: Option<String>,
+
+    #[clap(flatten)]
+    pub limits: InstallLimitOpts,
 }
 
 #[derive(ArgEnum, Debug, PartialEq, Clone)]
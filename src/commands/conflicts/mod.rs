@@ -0,0 +1,59 @@
+use crate::cli::ConflictsSubCommand;
+use crate::state::*;
+use crate::util::{print_table, GlobalFolders};
+use std::collections::{BTreeMap, BTreeSet};
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Error, Debug)]
+pub enum ConflictsError {
+    #[error(transparent)]
+    State(#[from] crate::state::StateError),
+    #[error(transparent)]
+    Uknown(#[from] anyhow::Error),
+}
+
+pub async fn handle_conflicts(
+    _conflicts_args: ConflictsSubCommand,
+    global_folder: &GlobalFolders,
+) -> Result<(), ConflictsError> {
+    let global_state = global_folder.global_state_file();
+    let container = get_current_state(&global_state).await?;
+    let state = &container.current_state;
+
+    let mut providers: BTreeMap<&String, BTreeSet<&String>> = BTreeMap::new();
+    for binary in state.installed_binaries.values() {
+        if let Some(package) = state.installed_packages.get(&binary.package_id) {
+            providers
+                .entry(&binary.name)
+                .or_default()
+                .insert(&package.name);
+        }
+    }
+
+    let rows: Vec<Vec<String>> = providers
+        .into_iter()
+        .filter(|(_, packages)| packages.len() > 1)
+        .map(|(binary, packages)| {
+            let packages: Vec<&str> = packages.into_iter().map(|x| x.as_str()).collect();
+            vec![
+                binary.clone(),
+                packages.join(", "),
+                state.link_owner(binary).unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+
+    if rows.is_empty() {
+        info!(target: "user", "No binaries are provided by more than one package.");
+        return Ok(());
+    }
+
+    print_table(&["BINARY", "PACKAGES", "LINKED TO"], rows);
+
+    Ok(())
+}
+
+pub mod prelude {
+    pub use super::{handle_conflicts, ConflictsError};
+}
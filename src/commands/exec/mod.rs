@@ -1,4 +1,5 @@
 use crate::cli::ExecSubCommand;
+use crate::resolve::{resolve_binary, ResolveError};
 use crate::state::*;
 use crate::util::exec;
 use thiserror::Error;
@@ -8,6 +9,10 @@ pub enum ExecError {
     #[error(transparent)]
     StateError(#[from] crate::state::StateError),
     #[error(transparent)]
+    Resolve(#[from] ResolveError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
     UknownError(#[from] anyhow::Error),
 }
 
@@ -18,16 +23,16 @@ pub async fn handle_exec(
     let global_state = global_folder.global_state_file();
     let container = get_current_state(&global_state).await?;
 
-    let path = match exec_args.version {
-        Some(version) => container
-            .current_state
-            .get_binary_path(&exec_args.command_name, &version)?,
-        None => container
-            .current_state
-            .get_current_binary_path(&exec_args.command_name)?,
-    };
+    let resolved = resolve_binary(
+        &container.current_state,
+        &exec_args.command_name,
+        exec_args.version.as_deref(),
+        &std::env::current_dir()?,
+    )?;
 
-    exec(path, exec_args.args);
+    let (env, mut args) = resolved.exec_options(&container.current_state)?;
+    args.extend(exec_args.args);
+    exec(resolved.binary.path_to_exec.clone(), args, env);
 
     unreachable!();
 }
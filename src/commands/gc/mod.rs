@@ -0,0 +1,275 @@
+use crate::cli::GcSubCommand;
+use crate::package::{uninstall_packages, PackageError, STAGING_MARKER};
+use crate::remote::{load_remote_packages, RemoteError, DOWNLOAD_MARKER};
+use crate::state::*;
+use crate::util::GlobalFolders;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tracing::{debug, info};
+
+/// Leftover files are only removed once they are this old, so in-flight installs are left alone.
+const STALE_AFTER_SECONDS: i64 = 60 * 60;
+
+#[derive(Error, Debug)]
+pub enum GcError {
+    #[error(transparent)]
+    State(#[from] crate::state::StateError),
+    #[error(transparent)]
+    Package(#[from] PackageError),
+    #[error(transparent)]
+    Remote(#[from] RemoteError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Uknown(#[from] anyhow::Error),
+}
+
+pub async fn handle_gc(
+    gc_args: GcSubCommand,
+    global_folder: &GlobalFolders,
+) -> Result<(), GcError> {
+    let global_state = global_folder.global_state_file();
+    let remote_packages = load_remote_packages(global_folder)?;
+
+    // Packages installed before sources were recorded need their remote to find its retention policy.
+    let mut container = get_current_state(&global_state).await?;
+    if container.migrate_package_sources(&remote_packages) && !gc_args.dry_run {
+        container = write_state(&global_state, container).await?;
+    }
+    let state = &container.current_state;
+
+    let keep_versions: BTreeMap<String, usize> = remote_packages
+        .into_iter()
+        .filter_map(|remote| remote.keep_versions.map(|keep| (remote.name, keep)))
+        .collect();
+
+    let package_names: BTreeSet<&String> =
+        state.installed_packages.values().map(|x| &x.name).collect();
+
+    let mut packages_to_remove = Vec::new();
+    for name in package_names {
+        let keep = state
+            .installed_versions(name)
+            .iter()
+            .filter_map(|x| x.remote_name.as_ref())
+            .find_map(|remote| keep_versions.get(remote).cloned())
+            .or(gc_args.keep_versions);
+
+        let keep = match keep {
+            Some(keep) => keep,
+            None => {
+                debug!("No retention policy for {}, keeping all versions", name);
+                continue;
+            }
+        };
+
+        for package in versions_to_remove(state, name, keep) {
+            if gc_args.dry_run {
+                info!(target: "user", "Would remove {}@{} from {}", package.name, package.version, package.package_dir);
+            }
+            packages_to_remove.push(package.id.clone());
+        }
+    }
+
+    if !gc_args.dry_run && !packages_to_remove.is_empty() {
+        uninstall_packages(&packages_to_remove, global_folder).await?;
+    }
+
+    let mut stale_paths =
+        find_stale_paths(Path::new(&global_folder.tool_root_dir), STAGING_MARKER)?;
+    stale_paths.extend(find_stale_paths(
+        &global_folder.get_remote_download_dir(),
+        DOWNLOAD_MARKER,
+    )?);
+
+    for path in stale_paths {
+        if gc_args.dry_run {
+            info!(target: "user", "Would remove {}", path.display());
+            continue;
+        }
+
+        info!(target: "user", "Removing {}", path.display());
+        if path.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The versions of `name` that aren't kept. The current version is always kept, and `keep` of
+/// the highest other versions are kept in addition to it.
+fn versions_to_remove<'a>(
+    state: &'a InstalledState,
+    name: &str,
+    keep: usize,
+) -> Vec<&'a InstalledPackage> {
+    let current_id = state.current_packages.get(name).map(|x| &x.id);
+    state
+        .installed_versions(name)
+        .into_iter()
+        .filter(|package| Some(&package.id) != current_id)
+        .skip(keep)
+        .collect()
+}
+
+/// Find entries in `dir` named `<anything><marker><timestamp>` whose timestamp is old enough to be abandoned.
+fn find_stale_paths(dir: &Path, marker: &str) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut stale = Vec::new();
+    if !dir.exists() {
+        return Ok(stale);
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        // The timestamp is followed by a random part, which is ignored.
+        let timestamp = match file_name.rsplit_once(marker) {
+            Some((_, suffix)) => suffix.split('.').next().and_then(|x| x.parse::<i64>().ok()),
+            None => None,
+        };
+
+        if let Some(timestamp) = timestamp {
+            if now - timestamp > STALE_AFTER_SECONDS {
+                stale.push(entry.path());
+            }
+        }
+    }
+
+    Ok(stale)
+}
+
+#[tokio::test]
+async fn gc_keeps_current_and_the_remotes_retention() {
+    use crate::model::{LocalPackageRepository, PackageRepository, RemotePackage};
+    use crate::package::{install_package, load_installed_package};
+    use crate::test_util::*;
+
+    let (temp_dir, global_folder) = temp_global_folder();
+    let global_state = global_folder.global_state_file();
+    for version in ["1.0.0", "2.0.0", "3.0.0", "4.0.0"] {
+        let archive = make_test_archive(&temp_dir, version, version).await;
+        install_package(&archive, false, false, &global_folder)
+            .await
+            .unwrap();
+    }
+
+    let mut container = get_current_state(&global_state).await.unwrap();
+    let package = container
+        .current_state
+        .resolve_version("foo", "2.0.0")
+        .unwrap();
+    let package = load_installed_package(package).unwrap();
+    container
+        .current_state
+        .make_package_current(&package, false)
+        .unwrap();
+    let container = write_state(&global_state, container).await.unwrap();
+
+    let state = &container.current_state;
+    let removed = |keep| {
+        versions_to_remove(state, "foo", keep)
+            .into_iter()
+            .map(|x| x.version.as_str())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec!["4.0.0", "3.0.0", "1.0.0"], removed(0));
+    assert_eq!(vec!["3.0.0", "1.0.0"], removed(1));
+    assert_eq!(vec!["1.0.0"], removed(2));
+    assert!(removed(3).is_empty());
+    assert!(versions_to_remove(state, "bar", 0).is_empty());
+
+    // These packages were installed without a remote, so gc has to attach it to use its retention
+    let remote = RemotePackage {
+        keep_versions: Some(1),
+        ..test_remote(
+            "foo",
+            PackageRepository::Local(LocalPackageRepository {
+                path: temp_dir.join("foo.tar.gz").display().to_string(),
+            }),
+        )
+    };
+    add_test_remote(&global_folder, &remote);
+
+    let gc_args = GcSubCommand {
+        keep_versions: None,
+        dry_run: true,
+    };
+    handle_gc(gc_args, &global_folder).await.unwrap();
+    let container = get_current_state(&global_state).await.unwrap();
+    let versions = container.current_state.installed_versions("foo");
+    assert_eq!(4, versions.len());
+    assert!(
+        versions.iter().all(|x| x.remote_name.is_none()),
+        "a dry run shouldn't change the state"
+    );
+
+    let gc_args = GcSubCommand {
+        keep_versions: None,
+        dry_run: false,
+    };
+    handle_gc(gc_args, &global_folder).await.unwrap();
+    let container = get_current_state(&global_state).await.unwrap();
+    let state = &container.current_state;
+    let versions: Vec<&str> = state
+        .installed_versions("foo")
+        .into_iter()
+        .map(|x| x.version.as_str())
+        .collect();
+    assert_eq!(vec!["4.0.0", "2.0.0"], versions);
+    assert_eq!("2.0.0", state.find_current_package("foo").unwrap().version);
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
+
+#[test]
+fn finds_only_old_leftovers() {
+    use crate::package::{backup_dir_name, staging_dir_name, verify_dir_name};
+    use crate::remote::download_file_name;
+
+    let (temp_dir, _) = crate::test_util::temp_global_folder();
+    let now = chrono::Utc::now().timestamp();
+    let old = now - STALE_AFTER_SECONDS - 60;
+    assert_ne!(staging_dir_name(now), staging_dir_name(now));
+    assert_ne!(
+        download_file_name("foo", now),
+        download_file_name("foo", now)
+    );
+
+    let stale_dirs = vec![
+        temp_dir.join(backup_dir_name(old)),
+        temp_dir.join(staging_dir_name(old)),
+        temp_dir.join(verify_dir_name(old)),
+    ];
+    for dir in &stale_dirs {
+        fs::create_dir_all(dir).unwrap();
+    }
+    fs::create_dir_all(temp_dir.join(staging_dir_name(now))).unwrap();
+    fs::create_dir_all(temp_dir.join("packages")).unwrap();
+
+    let stale_download = temp_dir.join(download_file_name("foo", old));
+    fs::write(&stale_download, "").unwrap();
+    fs::write(temp_dir.join(download_file_name("bar", now)), "").unwrap();
+
+    let mut found = find_stale_paths(&temp_dir, STAGING_MARKER).unwrap();
+    found.sort();
+    assert_eq!(stale_dirs, found);
+    assert_eq!(
+        vec![stale_download],
+        find_stale_paths(&temp_dir, DOWNLOAD_MARKER).unwrap()
+    );
+    assert!(find_stale_paths(&temp_dir.join("missing"), STAGING_MARKER)
+        .unwrap()
+        .is_empty());
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
+
+pub mod prelude {
+    pub use super::{handle_gc, GcError};
+}
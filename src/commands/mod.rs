@@ -1,18 +1,30 @@
+use crate::cli::InstallLimitOpts;
+use crate::model::InstallLimits;
 use crate::util::GlobalFolders;
 use async_trait::async_trait;
 use thiserror::Error;
 
 mod config;
+mod conflicts;
 mod exec;
+mod gc;
 mod package;
 mod remote;
+mod use_version;
+mod verify;
 mod version;
+mod which;
 
 pub use config::prelude::*;
+pub use conflicts::prelude::*;
 pub use exec::prelude::*;
+pub use gc::prelude::*;
 pub use package::prelude::*;
 pub use remote::prelude::*;
+pub use use_version::prelude::*;
+pub use verify::prelude::*;
 pub use version::print_version;
+pub use which::prelude::*;
 
 #[derive(Error, Debug)]
 pub enum CommandError {
@@ -25,6 +37,16 @@ pub enum CommandError {
     #[error(transparent)]
     ConfigError(#[from] ConfigError),
     #[error(transparent)]
+    UseError(#[from] UseError),
+    #[error(transparent)]
+    GcError(#[from] GcError),
+    #[error(transparent)]
+    WhichError(#[from] WhichError),
+    #[error(transparent)]
+    ConflictsError(#[from] ConflictsError),
+    #[error(transparent)]
+    VerifyError(#[from] VerifyError),
+    #[error(transparent)]
     UknownError(#[from] anyhow::Error),
 }
 
@@ -32,3 +54,24 @@ pub enum CommandError {
 pub trait SubCommandExec<E> {
     async fn execute(self, global_folder: &GlobalFolders) -> Result<(), E>;
 }
+
+/// The install limits given on the command line, on top of the defaults. `None` when none were given.
+fn install_limits(opts: &InstallLimitOpts) -> Option<InstallLimits> {
+    if opts.max_entries.is_none()
+        && opts.max_total_mib.is_none()
+        && opts.max_compression_ratio.is_none()
+    {
+        return None;
+    }
+
+    let defaults = InstallLimits::default();
+    Some(InstallLimits {
+        max_entries: opts.max_entries.unwrap_or(defaults.max_entries),
+        max_total_bytes: opts
+            .max_total_mib
+            .map_or(defaults.max_total_bytes, |x| x.saturating_mul(1024 * 1024)),
+        max_compression_ratio: opts
+            .max_compression_ratio
+            .unwrap_or(defaults.max_compression_ratio),
+    })
+}
@@ -11,8 +11,12 @@ use walkdir::WalkDir;
 
 use crate::cli::*;
 use crate::commands::SubCommandExec;
-use crate::model::{GeneratedDefinedPackage, UserDefinedPackage, GENERATED_FILE_NAME};
-use crate::util::GlobalFolders;
+use crate::model::{
+    EntrypointDefinition, GeneratedDefinedPackage, PackageTarget, UserDefinedPackage,
+    GENERATED_FILE_NAME, SIGNATURE_FILE_NAME,
+};
+use crate::signing::{read_signing_key, sign, SigningError};
+use crate::util::{GlobalFolders, HashingReader};
 
 #[derive(Error, Debug)]
 pub enum ArchivePackageError {
@@ -20,9 +24,27 @@ pub enum ArchivePackageError {
     TargetDoesNotExist { target: String },
     #[error("`{target}` is not a file.")]
     TargetIsNotFile { target: String },
+    #[error("`{version}` is not a semver version. Use --allow-non-semver to archive it anyway.")]
+    InvalidVersion { version: String },
+    #[error("The files in the package are {size} bytes uncompressed, which is over the limit of {limit} bytes.")]
+    PackageTooLarge { size: u64, limit: u64 },
+    #[error("`{file}` is {size} bytes, which is over the per-file limit of {limit} bytes.")]
+    FileTooLarge { file: String, size: u64, limit: u64 },
+    #[error("More than one entrypoint is named `{name}`.")]
+    DuplicateEntrypoint { name: String },
+    #[error("`{name}` can't be used as an entrypoint name, it must be a file name without any directories.")]
+    InvalidEntrypointName { name: String },
+    #[error("`entrypoint_options` has options for `{name}`, which isn't an entrypoint. Aliases use the options of their entrypoint.")]
+    UnknownEntrypoint { name: String },
+    #[error("`{version_req}` isn't a valid version range for the dependency on `{name}`.")]
+    InvalidDependency { name: String, version_req: String },
+    #[error("`{target}` isn't a valid target. Targets look like `<os>-<arch>[-<libc>]`, for example `linux-x86_64-gnu`.")]
+    InvalidTarget { target: String },
     #[error("Unable to process {dir} due to {err}.")]
     UnableToWalkDir { dir: String, err: walkdir::Error },
     #[error(transparent)]
+    SigningError(#[from] SigningError),
+    #[error(transparent)]
     TomlDeError(#[from] toml::de::Error),
     #[error(transparent)]
     JsonSeError(#[from] serde_json::Error),
@@ -32,88 +54,248 @@ pub enum ArchivePackageError {
     UknownError(#[from] anyhow::Error),
 }
 
+const BYTES_PER_MIB: u64 = 1024 * 1024;
+
+/// Options that control what `archive_package` will accept.
+#[derive(Debug, Clone)]
+pub struct ArchiveOptions {
+    /// Allow versions that aren't semver.
+    pub allow_non_semver: bool,
+    /// The largest total uncompressed size of the files in the package.
+    pub max_total_bytes: u64,
+    /// The largest any one file in the package can be.
+    pub max_file_bytes: Option<u64>,
+    /// A secret key to sign `archive.json` with.
+    pub sign_key: Option<PathBuf>,
+    /// The platform the package is built for, replacing any `target` in the config file.
+    pub target: Option<PackageTarget>,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        Self {
+            allow_non_semver: false,
+            max_total_bytes: 128 * BYTES_PER_MIB,
+            max_file_bytes: None,
+            sign_key: None,
+            target: None,
+        }
+    }
+}
+
 #[async_trait]
 impl SubCommandExec<ArchivePackageError> for ArchiveToolSubCommand {
     async fn execute(self, _global_folder: &GlobalFolders) -> Result<(), ArchivePackageError> {
-        debug!("Reading definition from {}", self.application_config);
+        let target = match &self.target {
+            Some(target) => Some(PackageTarget::parse(target).ok_or_else(|| {
+                ArchivePackageError::InvalidTarget {
+                    target: target.clone(),
+                }
+            })?),
+            None => None,
+        };
 
-        let application_config_path = Path::new(&self.application_config);
-        if !application_config_path.exists() {
-            return Err(ArchivePackageError::TargetDoesNotExist {
-                target: self.target_dir,
-            });
-        }
+        let options = ArchiveOptions {
+            allow_non_semver: self.allow_non_semver,
+            max_total_bytes: self.max_size_mib.saturating_mul(BYTES_PER_MIB),
+            max_file_bytes: self
+                .max_file_size_mib
+                .map(|x| x.saturating_mul(BYTES_PER_MIB)),
+            sign_key: self.sign_key.map(PathBuf::from),
+            target,
+        };
 
-        if !application_config_path.is_file() {
-            return Err(ArchivePackageError::TargetIsNotFile {
-                target: self.target_dir,
-            });
-        }
+        archive_package(
+            &self.application_config,
+            &self.target_dir,
+            &self.archive_dir,
+            &options,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Package up `target_dir` based on the config file, and write the archive to `archive_dir`.
+///
+/// Returns the path to the archive that was created.
+pub async fn archive_package(
+    application_config: &str,
+    target_dir: &str,
+    archive_dir: &str,
+    options: &ArchiveOptions,
+) -> Result<PathBuf, ArchivePackageError> {
+    debug!("Reading definition from {}", application_config);
+
+    let application_config_path = Path::new(application_config);
+    if !application_config_path.exists() {
+        return Err(ArchivePackageError::TargetDoesNotExist {
+            target: application_config.to_string(),
+        });
+    }
+
+    if !application_config_path.is_file() {
+        return Err(ArchivePackageError::TargetIsNotFile {
+            target: application_config.to_string(),
+        });
+    }
 
-        let definition = read_to_string(&application_config_path)?;
-        let definition: UserDefinedPackage = toml::from_str(&definition)?;
-        let mut files_to_package: BTreeMap<String, String> = BTreeMap::default();
+    let definition = read_to_string(&application_config_path)?;
+    let mut definition: UserDefinedPackage = toml::from_str(&definition)?;
+    if options.target.is_some() {
+        definition.target = options.target.clone();
+    }
 
-        let target_dir = Path::new(&self.target_dir);
-        if !target_dir.exists() {
-            return Err(ArchivePackageError::TargetDoesNotExist {
-                target: self.target_dir,
+    for (name, version_req) in &definition.dependencies {
+        if crate::util::parse_version_req(version_req).is_err() {
+            return Err(ArchivePackageError::InvalidDependency {
+                name: name.clone(),
+                version_req: version_req.clone(),
             });
         }
+    }
 
-        let target_dir = std::fs::canonicalize(target_dir)?;
-        let target_dir_absolute_path = target_dir.display().to_string();
+    if !options.allow_non_semver && semver::Version::parse(definition.version).is_err() {
+        return Err(ArchivePackageError::InvalidVersion {
+            version: definition.version.to_string(),
+        });
+    }
 
-        let mut entrypoint_paths = Vec::new();
+    let sign_key = match &options.sign_key {
+        Some(path) => Some(read_signing_key(path)?),
+        None => None,
+    };
 
-        for entrypoint in &definition.entrypoints {
-            entrypoint_paths.push(validate_entrypoint(entrypoint, &target_dir)?);
-        }
+    let mut files_to_package: BTreeMap<String, String> = BTreeMap::default();
+    let mut total_size: u64 = 0;
 
-        for entry in WalkDir::new(&target_dir) {
-            let entry = match entry {
-                Ok(entry) => entry,
-                Err(e) => {
-                    return Err(ArchivePackageError::UnableToWalkDir {
-                        dir: target_dir_absolute_path,
-                        err: e,
-                    });
-                }
-            };
+    let target_dir = Path::new(target_dir);
+    if !target_dir.exists() {
+        return Err(ArchivePackageError::TargetDoesNotExist {
+            target: target_dir.display().to_string(),
+        });
+    }
 
-            if entry.file_type().is_dir() {
-                continue;
-            }
+    let target_dir = std::fs::canonicalize(target_dir)?;
+    let target_dir_absolute_path = target_dir.display().to_string();
+
+    let mut entrypoints = BTreeMap::new();
 
-            let entry_path = entry
-                .path()
-                .strip_prefix(&target_dir)
-                .expect("Base path to be well known");
-            files_to_package.insert(
-                entry_path.display().to_string(),
-                entry.path().display().to_string(),
+    for entrypoint in &definition.entrypoints {
+        let entrypoint_path = validate_entrypoint(entrypoint.path(), &target_dir)?;
+        for name in entrypoint.names() {
+            // The name becomes a link in the link dir, so it can't be a path.
+            let mut components = Path::new(&name).components();
+            let is_file_name = matches!(
+                (components.next(), components.next()),
+                (Some(Component::Normal(x)), None) if x == name.as_str()
             );
+            if !is_file_name {
+                return Err(ArchivePackageError::InvalidEntrypointName { name });
+            }
+
+            if entrypoints
+                .insert(name.clone(), entrypoint_path.clone())
+                .is_some()
+            {
+                return Err(ArchivePackageError::DuplicateEntrypoint { name });
+            }
         }
+    }
 
-        debug!("Files to include in archive are: {:?}", files_to_package);
+    let walker = WalkDir::new(&target_dir)
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != ".git");
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                return Err(ArchivePackageError::UnableToWalkDir {
+                    dir: target_dir_absolute_path,
+                    err: e,
+                });
+            }
+        };
 
-        let archive_path = Path::new(&self.archive_dir).join(format!(
-            "{name}-{version}.tar.gz",
-            name = definition.name.replace(' ', "_"),
-            version = definition.version
-        ));
+        if entry.file_type().is_dir() {
+            continue;
+        }
 
-        let archive = create_archive(entrypoint_paths, &definition, files_to_package).await?;
+        let entry_path = entry
+            .path()
+            .strip_prefix(&target_dir)
+            .expect("Base path to be well known");
 
-        debug!("Compressing files");
-        let mut e = GzEncoder::new(File::create(&archive_path)?, Compression::default());
-        e.write_all(&archive)?;
-        e.finish()?;
+        let size = entry
+            .metadata()
+            .map_err(|e| ArchivePackageError::UnableToWalkDir {
+                dir: target_dir_absolute_path.clone(),
+                err: e,
+            })?
+            .len();
+        if let Some(limit) = options.max_file_bytes {
+            if size > limit {
+                return Err(ArchivePackageError::FileTooLarge {
+                    file: entry_path.display().to_string(),
+                    size,
+                    limit,
+                });
+            }
+        }
 
-        info!(target: "user", "Finished creating artifact at path {}", archive_path.display().to_string());
+        total_size = total_size.saturating_add(size);
+        if total_size > options.max_total_bytes {
+            return Err(ArchivePackageError::PackageTooLarge {
+                size: total_size,
+                limit: options.max_total_bytes,
+            });
+        }
 
-        Ok(())
+        files_to_package.insert(
+            entry_path.display().to_string(),
+            entry.path().display().to_string(),
+        );
     }
+
+    debug!("Files to include in archive are: {:?}", files_to_package);
+
+    let target = match &definition.target {
+        Some(target) => format!("-{}", target),
+        None => String::new(),
+    };
+    let archive_path = Path::new(archive_dir).join(format!(
+        "{name}-{version}{target}.tar.gz",
+        name = definition.name.replace(' ', "_"),
+        version = definition.version,
+        target = target
+    ));
+
+    // Files are streamed into the archive, so write to a temp file until the archive is complete.
+    let partial_path = archive_path.with_extension("gz.partial");
+    let encoder = GzEncoder::new(File::create(&partial_path)?, Compression::default());
+    let result = create_archive(
+        entrypoints,
+        &definition,
+        files_to_package,
+        sign_key.as_ref(),
+        encoder,
+    )
+    .await;
+    match result {
+        Ok(encoder) => {
+            encoder.finish()?;
+            std::fs::rename(&partial_path, &archive_path)?;
+        }
+        Err(e) => {
+            std::fs::remove_file(&partial_path)?;
+            return Err(e);
+        }
+    }
+
+    info!(target: "user", "Finished creating artifact at path {}", archive_path.display().to_string());
+
+    Ok(archive_path)
 }
 
 fn validate_entrypoint(
@@ -136,52 +318,69 @@ fn validate_entrypoint(
     Ok(entrypoint_path)
 }
 
-#[instrument(skip(entrypoint_paths, package, artifacts))]
-async fn create_archive(
-    entrypoint_paths: Vec<String>,
+/// Stream every file into a tar written to `output`, hashing them on the way through.
+#[instrument(skip(entrypoints, package, artifacts, sign_key, output))]
+async fn create_archive<W: Write + Send>(
+    entrypoints: BTreeMap<String, String>,
     package: &UserDefinedPackage<'_>,
     artifacts: BTreeMap<String, String>,
-) -> Result<Vec<u8>, ArchivePackageError> {
+    sign_key: Option<&ed25519_dalek::SigningKey>,
+    output: W,
+) -> Result<W, ArchivePackageError> {
     use std::convert::TryInto;
     use tar::{Builder, Header};
 
-    let mut entrypoint_map = BTreeMap::new();
-    for entrypoint in entrypoint_paths {
-        let command_name = Path::new(&entrypoint)
-            .file_name()
-            .expect("The state file to have a valid filename")
-            .to_os_string()
-            .into_string()
-            .expect("State file to have a valid filename.");
-        entrypoint_map.insert(command_name, entrypoint);
+    let mut aliases = BTreeMap::new();
+    for entrypoint in &package.entrypoints {
+        if let EntrypointDefinition::Named {
+            name,
+            aliases: names,
+            ..
+        } = entrypoint
+        {
+            for alias in names {
+                aliases.insert(alias.clone(), name.clone());
+            }
+        }
+    }
+
+    if let Some(name) = package
+        .entrypoint_options
+        .keys()
+        .find(|name| !entrypoints.contains_key(*name) || aliases.contains_key(*name))
+    {
+        return Err(ArchivePackageError::UnknownEntrypoint { name: name.clone() });
     }
 
-    let mut archive = Builder::new(Vec::new());
+    let mut archive = Builder::new(output);
     let mut definition = GeneratedDefinedPackage {
         name: package.name.to_string(),
-        entrypoints: entrypoint_map,
+        entrypoints,
         version: package.version.to_string(),
         file_hashes: Default::default(),
         achived_at: chrono::Utc::now(),
+        env: package.env.clone(),
+        entrypoint_options: package.entrypoint_options.clone(),
+        aliases,
+        target: package.target.clone(),
+        dependencies: package.dependencies.clone(),
     };
 
     for (archive_name, file_path) in artifacts.into_iter() {
         info!("Archiving {}", archive_name);
-        let mut buffer = Vec::new();
-        let mut file = File::open(file_path)?;
-        let size = file.read_to_end(&mut buffer)?;
-
-        let hex = crate::util::get_hash_for_contents(buffer.clone());
-        definition.file_hashes.insert(archive_name.clone(), hex);
+        let file = File::open(file_path)?;
+        let metadata = file.metadata()?;
 
         let mut header = Header::new_gnu();
-        header.set_size(size.try_into().unwrap());
-        header.set_metadata(&file.metadata()?);
+        header.set_metadata(&metadata);
+        header.set_size(metadata.len());
         header.set_gid(1000);
         header.set_uid(1000);
         header.set_cksum();
 
-        archive.append_data(&mut header, archive_name, &mut buffer.as_slice())?;
+        let mut reader = HashingReader::new(file);
+        archive.append_data(&mut header, &archive_name, &mut reader)?;
+        definition.file_hashes.insert(archive_name, reader.hash());
     }
 
     let definition_stream = serde_json::to_string_pretty(&definition)?;
@@ -196,5 +395,129 @@ async fn create_archive(
     header.set_mtime(definition.achived_at.timestamp().try_into().unwrap());
     archive.append_data(&mut header, GENERATED_FILE_NAME, definition_stream)?;
 
+    if let Some(sign_key) = sign_key {
+        let signature = serde_json::to_string_pretty(&sign(sign_key, definition_stream))?;
+        let signature = signature.as_bytes();
+        header.set_size(signature.len().try_into().unwrap());
+        archive.append_data(&mut header, SIGNATURE_FILE_NAME, signature)?;
+    }
+
     Ok(archive.into_inner()?)
 }
+
+#[tokio::test]
+async fn archive_enforces_size_limits() {
+    let temp_dir = std::env::temp_dir().join(format!("toolup-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&temp_dir).unwrap();
+    std::fs::write(temp_dir.join("foo"), "a".repeat(2048)).unwrap();
+    std::fs::write(
+        temp_dir.join("package.toml"),
+        "name = \"foo\"\nversion = \"1.0.0\"\nentrypoints = [\"foo\"]\n",
+    )
+    .unwrap();
+
+    let config = temp_dir.join("package.toml").display().to_string();
+    let target_dir = temp_dir.display().to_string();
+    let archive = |options: ArchiveOptions| {
+        let config = config.clone();
+        let target_dir = target_dir.clone();
+        async move { archive_package(&config, &target_dir, &target_dir, &options).await }
+    };
+
+    let result = archive(ArchiveOptions {
+        max_total_bytes: 1024,
+        ..Default::default()
+    })
+    .await;
+    assert!(matches!(
+        result,
+        Err(ArchivePackageError::PackageTooLarge { limit: 1024, .. })
+    ));
+
+    let result = archive(ArchiveOptions {
+        max_file_bytes: Some(1024),
+        ..Default::default()
+    })
+    .await;
+    assert!(matches!(
+        result,
+        Err(ArchivePackageError::FileTooLarge { ref file, .. }) if file == "foo"
+    ));
+
+    assert!(archive(ArchiveOptions::default()).await.is_ok());
+
+    std::fs::remove_dir_all(temp_dir).unwrap();
+}
+
+#[tokio::test]
+async fn archive_rejects_duplicate_entrypoint_names() {
+    let temp_dir = std::env::temp_dir().join(format!("toolup-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(temp_dir.join("bin")).unwrap();
+    std::fs::write(temp_dir.join("bin/foo-linux-amd64"), "foo").unwrap();
+    std::fs::write(temp_dir.join("bin/foo"), "foo").unwrap();
+
+    let archive = |entrypoints: &str| {
+        let config = temp_dir.join(format!("{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &config,
+            format!(
+                "name = \"foo\"\nversion = \"1.0.0\"\nentrypoints = {}\n",
+                entrypoints
+            ),
+        )
+        .unwrap();
+        let target_dir = temp_dir.display().to_string();
+        async move {
+            archive_package(
+                &config.display().to_string(),
+                &target_dir,
+                &target_dir,
+                &Default::default(),
+            )
+            .await
+        }
+    };
+
+    let path = archive(r#"[{ name = "foo", path = "bin/foo-linux-amd64", aliases = ["f"] }]"#)
+        .await
+        .unwrap();
+    let definition = crate::package::read_archive_definition(&path, &[]).unwrap();
+    assert_eq!(Some(&"foo".to_string()), definition.aliases.get("f"));
+    assert_eq!(
+        definition.entrypoints.get("foo"),
+        definition.entrypoints.get("f")
+    );
+
+    // Options go on the entrypoint, and its aliases use them
+    let result = archive(
+        r#"[{ name = "foo", path = "bin/foo-linux-amd64", aliases = ["f"] }]
+[entrypoint_options.f]
+args = ["--verbose"]"#,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(ArchivePackageError::UnknownEntrypoint { ref name }) if name == "f"
+    ));
+
+    let result = archive(r#"["bin/foo", { name = "foo", path = "bin/foo-linux-amd64" }]"#).await;
+    assert!(matches!(
+        result,
+        Err(ArchivePackageError::DuplicateEntrypoint { ref name }) if name == "foo"
+    ));
+
+    let result =
+        archive(r#"[{ name = "foo", path = "bin/foo-linux-amd64", aliases = ["foo"] }]"#).await;
+    assert!(matches!(
+        result,
+        Err(ArchivePackageError::DuplicateEntrypoint { .. })
+    ));
+
+    let result = archive(r#"[{ name = "../foo", path = "bin/foo-linux-amd64" }]"#).await;
+    assert!(matches!(
+        result,
+        Err(ArchivePackageError::InvalidEntrypointName { .. })
+    ));
+
+    std::fs::remove_dir_all(temp_dir).unwrap();
+}
@@ -0,0 +1,42 @@
+use async_trait::async_trait;
+use std::fs::OpenOptions;
+use std::io::Write;
+use thiserror::Error;
+use tracing::info;
+
+use crate::cli::*;
+use crate::commands::SubCommandExec;
+use crate::signing::generate_key;
+use crate::util::GlobalFolders;
+
+#[derive(Error, Debug)]
+pub enum GenerateKeyError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Uknown(#[from] anyhow::Error),
+}
+
+#[async_trait]
+impl SubCommandExec<GenerateKeyError> for GenerateKeySubCommand {
+    async fn execute(self, _global_folder: &GlobalFolders) -> Result<(), GenerateKeyError> {
+        let (secret_key, public_key) = generate_key();
+
+        // Never replace an existing key, packages signed with it would no longer be trusted.
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options
+            .open(&self.output)?
+            .write_all(secret_key.as_bytes())?;
+
+        info!(target: "user", "Wrote the secret key to {}. Use this public key with --trusted-key:", self.output);
+        println!("{}", public_key);
+
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@ use thiserror::Error;
 
 use crate::cli::*;
 use crate::commands::SubCommandExec;
-use crate::model::UserDefinedPackage;
+use crate::model::{EntrypointDefinition, UserDefinedPackage};
 use crate::util::GlobalFolders;
 
 #[derive(Error, Debug)]
@@ -23,8 +23,12 @@ impl SubCommandExec<InitPackageError> for InitToolSubCommand {
     async fn execute(self, _global_folder: &GlobalFolders) -> Result<(), InitPackageError> {
         let udp = UserDefinedPackage {
             name: "clu",
-            entrypoints: vec!["clu"],
+            entrypoints: vec![EntrypointDefinition::Path("clu".to_string())],
             version: "1.0.0",
+            env: Default::default(),
+            entrypoint_options: Default::default(),
+            target: None,
+            dependencies: Default::default(),
         };
 
         let definition = toml::to_string_pretty(&udp)?;
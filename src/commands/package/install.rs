@@ -3,8 +3,9 @@ use std::path::*;
 use thiserror::Error;
 
 use crate::cli::*;
-use crate::commands::SubCommandExec;
-use crate::package::{install_package, PackageError};
+use crate::commands::{install_limits, SubCommandExec};
+use crate::dependencies::{install_with_dependencies, DependencyError};
+use crate::package::PackageError;
 use crate::remote::DownloadedArtifact;
 use crate::state::{get_current_state, update_links};
 use crate::util::GlobalFolders;
@@ -16,6 +17,8 @@ pub enum InstallPackageError {
     #[error(transparent)]
     Package(#[from] PackageError),
     #[error(transparent)]
+    Dependency(#[from] DependencyError),
+    #[error(transparent)]
     Uknown(#[from] anyhow::Error),
 }
 
@@ -25,8 +28,13 @@ impl SubCommandExec<InstallPackageError> for InstallToolSubCommand {
         let archive_path = DownloadedArtifact {
             path: PathBuf::from(&self.archive_path),
             etag: None,
+            last_modified: None,
+            source: None,
+            trusted_keys: self.trusted_keys,
+            install_limits: install_limits(&self.limits).unwrap_or_default(),
         };
-        install_package(&archive_path, self.overwrite, global_folder).await?;
+        install_with_dependencies(&archive_path, self.overwrite, self.take_over, global_folder)
+            .await?;
 
         let global_state = global_folder.global_state_file();
         let container = get_current_state(&global_state).await?;
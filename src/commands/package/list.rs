@@ -0,0 +1,76 @@
+use async_trait::async_trait;
+use thiserror::Error;
+use tracing::info;
+
+use crate::cli::*;
+use crate::commands::SubCommandExec;
+use crate::state::get_current_state;
+use crate::util::{print_table, GlobalFolders};
+
+#[derive(Error, Debug)]
+pub enum ListPackageError {
+    #[error(transparent)]
+    State(#[from] crate::state::StateError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Uknown(#[from] anyhow::Error),
+}
+
+#[async_trait]
+impl SubCommandExec<ListPackageError> for ListPackageSubCommand {
+    async fn execute(self, global_folder: &GlobalFolders) -> Result<(), ListPackageError> {
+        let global_state = global_folder.global_state_file();
+        let container = get_current_state(&global_state).await?;
+        let packages = container.list_installed_packages();
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&packages)?);
+            return Ok(());
+        }
+
+        if packages.is_empty() {
+            info!(target: "user", "No packages are installed.");
+            return Ok(());
+        }
+
+        let rows = packages
+            .into_iter()
+            .map(|package| {
+                let binaries: Vec<String> = package
+                    .binaries
+                    .iter()
+                    .map(|(key, value)| {
+                        if *value {
+                            format!("{} (current)", key)
+                        } else {
+                            key.to_string()
+                        }
+                    })
+                    .collect();
+                vec![
+                    package.name,
+                    package.version,
+                    package.remote_name.unwrap_or_else(|| "-".to_string()),
+                    package.etag.unwrap_or_else(|| "-".to_string()),
+                    binaries.join(", "),
+                    package.package_dir,
+                ]
+            })
+            .collect();
+
+        print_table(
+            &[
+                "NAME",
+                "VERSION",
+                "REMOTE",
+                "ETAG",
+                "BINARIES",
+                "INSTALL DIR",
+            ],
+            rows,
+        );
+
+        Ok(())
+    }
+}
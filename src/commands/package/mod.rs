@@ -1,20 +1,35 @@
 mod create;
+mod generate_key;
 mod init;
 mod install;
+mod list;
+mod remove;
+mod show;
+mod verify;
 
 use crate::cli::PackageSubCommand;
 use crate::commands::SubCommandExec;
 use crate::util::GlobalFolders;
 pub use create::ArchivePackageError;
+pub use generate_key::GenerateKeyError;
 pub use init::InitPackageError;
 pub use install::InstallPackageError;
+pub use list::ListPackageError;
+pub use remove::RemovePackageError;
+pub use show::ShowPackageError;
 use thiserror::Error;
+pub use verify::VerifyPackageError;
 
 pub mod prelude {
-    pub use super::create::ArchivePackageError;
+    pub use super::create::{archive_package, ArchiveOptions, ArchivePackageError};
+    pub use super::generate_key::GenerateKeyError;
     pub use super::handle_package;
     pub use super::init::InitPackageError;
     pub use super::install::InstallPackageError;
+    pub use super::list::ListPackageError;
+    pub use super::remove::RemovePackageError;
+    pub use super::show::ShowPackageError;
+    pub use super::verify::VerifyPackageError;
     pub use super::PackageError;
 }
 
@@ -27,6 +42,16 @@ pub enum PackageError {
     #[error(transparent)]
     Install(#[from] InstallPackageError),
     #[error(transparent)]
+    List(#[from] ListPackageError),
+    #[error(transparent)]
+    Remove(#[from] RemovePackageError),
+    #[error(transparent)]
+    Show(#[from] ShowPackageError),
+    #[error(transparent)]
+    Verify(#[from] VerifyPackageError),
+    #[error(transparent)]
+    GenerateKey(#[from] GenerateKeyError),
+    #[error(transparent)]
     Uknown(#[from] anyhow::Error),
 }
 
@@ -38,6 +63,11 @@ pub async fn handle_package(
         PackageSubCommand::Init(args) => args.execute(global_folder).await?,
         PackageSubCommand::Archive(args) => args.execute(global_folder).await?,
         PackageSubCommand::Install(args) => args.execute(global_folder).await?,
+        PackageSubCommand::List(args) => args.execute(global_folder).await?,
+        PackageSubCommand::Remove(args) => args.execute(global_folder).await?,
+        PackageSubCommand::Show(args) => args.execute(global_folder).await?,
+        PackageSubCommand::Verify(args) => args.execute(global_folder).await?,
+        PackageSubCommand::GenerateKey(args) => args.execute(global_folder).await?,
     };
 
     Ok(())
@@ -0,0 +1,85 @@
+use async_trait::async_trait;
+use std::collections::BTreeSet;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::cli::*;
+use crate::commands::SubCommandExec;
+use crate::dependencies::find_dependents;
+use crate::package::{uninstall_packages, PackageError};
+use crate::state::{get_current_state, InstalledState};
+use crate::util::{split_package_spec, version_matches, GlobalFolders};
+
+#[derive(Error, Debug)]
+pub enum RemovePackageError {
+    #[error("No versions of {name} are installed.")]
+    NothingToRemove { name: String },
+    #[error(transparent)]
+    State(#[from] crate::state::StateError),
+    #[error(transparent)]
+    Package(#[from] PackageError),
+    #[error(transparent)]
+    Uknown(#[from] anyhow::Error),
+}
+
+#[async_trait]
+impl SubCommandExec<RemovePackageError> for RemovePackageSubCommand {
+    async fn execute(self, global_folder: &GlobalFolders) -> Result<(), RemovePackageError> {
+        let global_state = global_folder.global_state_file();
+        let container = get_current_state(&global_state).await?;
+
+        let package_ids: Vec<String> = match split_package_spec(&self.package) {
+            (name, Some(version)) => {
+                vec![container
+                    .current_state
+                    .find_package(name, version)?
+                    .id
+                    .clone()]
+            }
+            (name, None) => container
+                .current_state
+                .installed_packages
+                .values()
+                .filter(|x| x.name == name)
+                .map(|x| x.id.clone())
+                .collect(),
+        };
+
+        if package_ids.is_empty() {
+            return Err(RemovePackageError::NothingToRemove { name: self.package });
+        }
+
+        warn_about_dependents(&container.current_state, &package_ids);
+        uninstall_packages(&package_ids, global_folder).await?;
+
+        Ok(())
+    }
+}
+
+/// Warn about every package that won't have a matching version of its dependencies once `package_ids` are removed.
+fn warn_about_dependents(state: &InstalledState, package_ids: &[String]) {
+    let names: BTreeSet<&String> = package_ids
+        .iter()
+        .filter_map(|id| state.installed_packages.get(id))
+        .map(|x| &x.name)
+        .collect();
+
+    for name in names {
+        let remaining: Vec<&String> = state
+            .installed_versions(name)
+            .into_iter()
+            .filter(|x| !package_ids.contains(&x.id))
+            .map(|x| &x.version)
+            .collect();
+
+        for (dependent, version_req) in find_dependents(state, name) {
+            if package_ids.contains(&dependent.id) {
+                continue;
+            }
+
+            if !remaining.iter().any(|x| version_matches(x, &version_req)) {
+                warn!(target: "user", "{}@{} depends on {} {}, which won't be installed anymore.", dependent.name, dependent.version, name, version_req);
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use async_trait::async_trait;
+use std::path::Path;
+use thiserror::Error;
+
+use crate::cli::*;
+use crate::commands::SubCommandExec;
+use crate::package::{read_package_definition, PackageError};
+use crate::state::get_current_state;
+use crate::util::{print_table, split_package_spec, GlobalFolders};
+
+#[derive(Error, Debug)]
+pub enum ShowPackageError {
+    #[error(transparent)]
+    State(#[from] crate::state::StateError),
+    #[error(transparent)]
+    Package(#[from] PackageError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Uknown(#[from] anyhow::Error),
+}
+
+#[async_trait]
+impl SubCommandExec<ShowPackageError> for ShowPackageSubCommand {
+    async fn execute(self, global_folder: &GlobalFolders) -> Result<(), ShowPackageError> {
+        let global_state = global_folder.global_state_file();
+        let container = get_current_state(&global_state).await?;
+
+        let package = match split_package_spec(&self.package) {
+            (name, Some(version)) => container.current_state.resolve_version(name, version)?,
+            (name, None) => container.current_state.find_current_package(name)?,
+        };
+
+        let definition = read_package_definition(Path::new(&package.package_dir))?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&definition)?);
+            return Ok(());
+        }
+
+        println!("Name:        {}", definition.name);
+        println!("Version:     {}", definition.version);
+        println!("Archived At: {}", definition.achived_at);
+        if let Some(target) = &definition.target {
+            println!("Target:      {}", target);
+        }
+        println!("Install Dir: {}", package.package_dir);
+        println!();
+
+        print_table(
+            &["ENTRYPOINT", "PATH"],
+            definition
+                .entrypoints
+                .into_iter()
+                .map(|(name, path)| vec![name, path])
+                .collect(),
+        );
+        println!();
+
+        if !definition.dependencies.is_empty() {
+            print_table(
+                &["DEPENDENCY", "VERSION"],
+                definition
+                    .dependencies
+                    .into_iter()
+                    .map(|(name, version_req)| vec![name, version_req])
+                    .collect(),
+            );
+            println!();
+        }
+
+        print_table(
+            &["FILE", "SHA256"],
+            definition
+                .file_hashes
+                .into_iter()
+                .map(|(name, hash)| vec![name, hash])
+                .collect(),
+        );
+
+        Ok(())
+    }
+}
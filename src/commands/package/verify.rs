@@ -0,0 +1,38 @@
+use async_trait::async_trait;
+use std::path::Path;
+use thiserror::Error;
+use tracing::info;
+
+use crate::cli::*;
+use crate::commands::{install_limits, SubCommandExec};
+use crate::package::{verify_archive, PackageError};
+use crate::util::GlobalFolders;
+
+#[derive(Error, Debug)]
+pub enum VerifyPackageError {
+    #[error(transparent)]
+    Package(#[from] PackageError),
+    #[error(transparent)]
+    Uknown(#[from] anyhow::Error),
+}
+
+#[async_trait]
+impl SubCommandExec<VerifyPackageError> for VerifyPackageSubCommand {
+    async fn execute(self, global_folder: &GlobalFolders) -> Result<(), VerifyPackageError> {
+        let (definition, signed_by) = verify_archive(
+            Path::new(&self.archive_path),
+            &self.trusted_keys,
+            &install_limits(&self.limits).unwrap_or_default(),
+            global_folder,
+        )
+        .await?;
+
+        info!(target: "user", "{}@{} in {} is valid.", definition.name, definition.version, self.archive_path);
+        match signed_by {
+            Some(key) => info!(target: "user", "It is signed by {}", key),
+            None => info!(target: "user", "It is not signed."),
+        }
+
+        Ok(())
+    }
+}
@@ -1,19 +1,37 @@
 use crate::cli::*;
-use crate::commands::SubCommandExec;
+use crate::commands::{install_limits, SubCommandExec};
 use crate::model::*;
-use crate::util::GlobalFolders;
+use crate::util::{parse_version_req, GlobalFolders};
 use async_trait::async_trait;
 use chrono::Duration;
+use std::collections::BTreeMap;
 use std::fs;
+use std::path::Path;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum AddRemoteError {
+    #[error("`{sha256}` isn't a sha256, expected 64 hex characters.")]
+    InvalidSha256 { sha256: String },
+    #[error(
+        "--sha256 can't be used with --target-url, each platform downloads a different archive."
+    )]
+    ChecksumWithTargetUrls,
+    #[error("--sha256 can't be used with --config, the archive is rebuilt with a new timestamp on every update.")]
+    ChecksumWithPackageConfig,
+    #[error("--sha256 can't be used with index remotes, the index already has a sha256 for each version.")]
+    ChecksumWithIndex,
+    #[error("`{value}` should look like `<target>=<url>`, with a target like `linux-x86_64-gnu`.")]
+    InvalidTargetUrl { value: String },
     #[error(transparent)]
     State(#[from] crate::state::StateError),
     #[error(transparent)]
     Json(#[from] serde_json::Error),
     #[error(transparent)]
+    Semver(#[from] semver::Error),
+    #[error(transparent)]
+    Signing(#[from] crate::signing::SigningError),
+    #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error(transparent)]
     Uknown(#[from] anyhow::Error),
@@ -25,6 +43,9 @@ impl SubCommandExec<AddRemoteError> for AddRemoteSubCommand {
         match self {
             AddRemoteSubCommand::Local(local) => local.execute(global_folder).await,
             AddRemoteSubCommand::S3(s3) => s3.execute(global_folder).await,
+            AddRemoteSubCommand::Http(http) => http.execute(global_folder).await,
+            AddRemoteSubCommand::Git(git) => git.execute(global_folder).await,
+            AddRemoteSubCommand::Index(index) => index.execute(global_folder).await,
         }
     }
 }
@@ -37,7 +58,11 @@ impl SubCommandExec<AddRemoteError> for AddRemoteLocalSubCommand {
         };
         let remote_package = RemotePackage {
             name: self.name.clone(),
-            update_period_seconds: Duration::days(1).num_seconds(),
+            update_period_seconds: self.options.update_period_seconds(),
+            keep_versions: self.options.keep_versions,
+            trusted_keys: self.options.trusted_keys.clone(),
+            sha256: self.options.sha256.clone(),
+            install_limits: install_limits(&self.options.limits),
             repository: PackageRepository::Local(local_package),
         };
 
@@ -58,22 +83,181 @@ impl SubCommandExec<AddRemoteError> for AddRemoteS3SubCommand {
 
         let s3_package = S3PackageRepository {
             url: self.url.clone(),
+            target_urls: parse_target_urls(&self.target_urls)?,
             auth_strategy,
         };
         let remote_package = RemotePackage {
             name: self.name.clone(),
-            update_period_seconds: Duration::days(1).num_seconds(),
+            update_period_seconds: self.options.update_period_seconds(),
+            keep_versions: self.options.keep_versions,
+            trusted_keys: self.options.trusted_keys.clone(),
+            sha256: self.options.sha256.clone(),
+            install_limits: install_limits(&self.options.limits),
             repository: PackageRepository::S3(s3_package),
         };
         add_remote_package(&self.name, remote_package, global_folder)
     }
 }
 
+#[async_trait]
+impl SubCommandExec<AddRemoteError> for AddRemoteHttpSubCommand {
+    async fn execute(self, global_folder: &GlobalFolders) -> Result<(), AddRemoteError> {
+        let http_package = HttpPackageRepository {
+            url: self.url.clone(),
+            target_urls: parse_target_urls(&self.target_urls)?,
+            auth_strategy: make_http_auth_strategy(self.auth, self.auth_script),
+        };
+        let remote_package = RemotePackage {
+            name: self.name.clone(),
+            update_period_seconds: self.options.update_period_seconds(),
+            keep_versions: self.options.keep_versions,
+            trusted_keys: self.options.trusted_keys.clone(),
+            sha256: self.options.sha256.clone(),
+            install_limits: install_limits(&self.options.limits),
+            repository: PackageRepository::Http(http_package),
+        };
+        add_remote_package(&self.name, remote_package, global_folder)
+    }
+}
+
+#[async_trait]
+impl SubCommandExec<AddRemoteError> for AddRemoteGitSubCommand {
+    async fn execute(self, global_folder: &GlobalFolders) -> Result<(), AddRemoteError> {
+        let artifact = match (self.config, self.archive_path) {
+            (Some(config_path), _) => {
+                let target_dir = match self.target_dir {
+                    Some(target_dir) => target_dir,
+                    None => Path::new(&config_path)
+                        .parent()
+                        .map(|x| x.display().to_string())
+                        .filter(|x| !x.is_empty())
+                        .unwrap_or_else(|| ".".to_string()),
+                };
+                GitArtifact::PackageConfig {
+                    config_path,
+                    target_dir,
+                }
+            }
+            (None, Some(archive_path)) => GitArtifact::Archive { archive_path },
+            (None, None) => unreachable!("clap requires either --config or --archive-path"),
+        };
+
+        let git_package = GitPackageRepository {
+            url: self.url.clone(),
+            reference: self.reference,
+            artifact,
+        };
+        let remote_package = RemotePackage {
+            name: self.name.clone(),
+            update_period_seconds: self.options.update_period_seconds(),
+            keep_versions: self.options.keep_versions,
+            trusted_keys: self.options.trusted_keys.clone(),
+            sha256: self.options.sha256.clone(),
+            install_limits: install_limits(&self.options.limits),
+            repository: PackageRepository::Git(git_package),
+        };
+        add_remote_package(&self.name, remote_package, global_folder)
+    }
+}
+
+#[async_trait]
+impl SubCommandExec<AddRemoteError> for AddRemoteIndexSubCommand {
+    async fn execute(self, global_folder: &GlobalFolders) -> Result<(), AddRemoteError> {
+        if let Some(version_req) = &self.version_req {
+            parse_version_req(version_req)?;
+        }
+
+        let index_package = IndexPackageRepository {
+            url: self.url.clone(),
+            channel: self.channel,
+            version_req: self.version_req,
+            auth_strategy: make_http_auth_strategy(self.auth, self.auth_script),
+        };
+        let remote_package = RemotePackage {
+            name: self.name.clone(),
+            update_period_seconds: self.options.update_period_seconds(),
+            keep_versions: self.options.keep_versions,
+            trusted_keys: self.options.trusted_keys.clone(),
+            sha256: self.options.sha256.clone(),
+            install_limits: install_limits(&self.options.limits),
+            repository: PackageRepository::Index(index_package),
+        };
+        add_remote_package(&self.name, remote_package, global_folder)
+    }
+}
+
+fn make_http_auth_strategy(auth: HttpAuthType, auth_script: Option<String>) -> HttpAuthStrategy {
+    match (auth, auth_script) {
+        (HttpAuthType::Bearer, Some(path)) => {
+            HttpAuthStrategy::Bearer(AuthScript { script_path: path })
+        }
+        (HttpAuthType::Basic, Some(path)) => {
+            HttpAuthStrategy::Basic(AuthScript { script_path: path })
+        }
+        _ => HttpAuthStrategy::None,
+    }
+}
+
+/// Parse `--target-url` values, which are written as `<target>=<url>`.
+fn parse_target_urls(values: &[String]) -> Result<BTreeMap<String, String>, AddRemoteError> {
+    let mut target_urls = BTreeMap::new();
+    for value in values {
+        let (target, url) = value
+            .split_once('=')
+            .filter(|(target, url)| PackageTarget::parse(target).is_some() && !url.is_empty())
+            .ok_or_else(|| AddRemoteError::InvalidTargetUrl {
+                value: value.clone(),
+            })?;
+        target_urls.insert(target.to_string(), url.to_string());
+    }
+
+    Ok(target_urls)
+}
+
+impl AddRemoteOptions {
+    fn update_period_seconds(&self) -> i64 {
+        self.update_period
+            .unwrap_or_else(|| Duration::days(1).num_seconds())
+    }
+}
+
 fn add_remote_package(
     name: &str,
     package: RemotePackage,
     global_folder: &GlobalFolders,
 ) -> Result<(), AddRemoteError> {
+    for key in &package.trusted_keys {
+        crate::signing::parse_public_key(key)?;
+    }
+
+    if let Some(sha256) = &package.sha256 {
+        let unpinnable = match &package.repository {
+            PackageRepository::S3(s3) if !s3.target_urls.is_empty() => {
+                Some(AddRemoteError::ChecksumWithTargetUrls)
+            }
+            PackageRepository::Http(http) if !http.target_urls.is_empty() => {
+                Some(AddRemoteError::ChecksumWithTargetUrls)
+            }
+            PackageRepository::Git(git) => match git.artifact {
+                GitArtifact::PackageConfig { .. } => {
+                    Some(AddRemoteError::ChecksumWithPackageConfig)
+                }
+                GitArtifact::Archive { .. } => None,
+            },
+            PackageRepository::Index(_) => Some(AddRemoteError::ChecksumWithIndex),
+            _ => None,
+        };
+        if let Some(e) = unpinnable {
+            return Err(e);
+        }
+
+        if sha256.len() != 64 || !sha256.chars().all(|x| x.is_ascii_hexdigit()) {
+            return Err(AddRemoteError::InvalidSha256 {
+                sha256: sha256.clone(),
+            });
+        }
+    }
+
     let pretty_json = serde_json::to_string_pretty(&package)?;
     let config_file = global_folder.make_remote_tool_config(name);
     let parent = config_file
@@ -84,3 +268,57 @@ fn add_remote_package(
     fs::write(config_file, pretty_json)?;
     Ok(())
 }
+
+#[cfg(test)]
+use crate::test_util::*;
+
+#[test]
+fn sha256_is_only_allowed_for_one_archive() {
+    let (temp_dir, global_folder) = temp_global_folder();
+    let sha256 = "ab".repeat(32);
+    let add = |repository: PackageRepository, sha256: &str| {
+        let remote = RemotePackage {
+            sha256: Some(sha256.to_string()),
+            ..test_remote("foo", repository)
+        };
+        add_remote_package("foo", remote, &global_folder)
+    };
+    let git = |artifact: GitArtifact| {
+        PackageRepository::Git(GitPackageRepository {
+            url: "https://example.com/foo.git".to_string(),
+            reference: None,
+            artifact,
+        })
+    };
+
+    let archive = GitArtifact::Archive {
+        archive_path: "foo.tar.gz".to_string(),
+    };
+    assert!(add(git(archive.clone()), &sha256).is_ok());
+    assert!(matches!(
+        add(git(archive), "abc"),
+        Err(AddRemoteError::InvalidSha256 { .. })
+    ));
+
+    let config = GitArtifact::PackageConfig {
+        config_path: "package.toml".to_string(),
+        target_dir: ".".to_string(),
+    };
+    assert!(matches!(
+        add(git(config), &sha256),
+        Err(AddRemoteError::ChecksumWithPackageConfig)
+    ));
+
+    let index = PackageRepository::Index(IndexPackageRepository {
+        url: "https://example.com/index.json".to_string(),
+        channel: None,
+        version_req: None,
+        auth_strategy: HttpAuthStrategy::None,
+    });
+    assert!(matches!(
+        add(index, &sha256),
+        Err(AddRemoteError::ChecksumWithIndex)
+    ));
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
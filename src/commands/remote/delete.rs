@@ -1,6 +1,8 @@
 use crate::cli::*;
 use crate::commands::SubCommandExec;
-use crate::state::{get_current_state, write_state};
+use crate::package::{uninstall_packages, PackageError};
+use crate::remote::load_remote_packages;
+use crate::state::get_current_state;
 use crate::util::GlobalFolders;
 use async_trait::async_trait;
 use std::fs;
@@ -13,6 +15,10 @@ pub enum DeleteRemoteError {
     #[error(transparent)]
     StateError(#[from] crate::state::StateError),
     #[error(transparent)]
+    PackageError(#[from] PackageError),
+    #[error(transparent)]
+    RemoteError(#[from] crate::remote::RemoteError),
+    #[error(transparent)]
     JsonError(#[from] serde_json::Error),
     #[error(transparent)]
     IoError(#[from] std::io::Error),
@@ -23,6 +29,7 @@ pub enum DeleteRemoteError {
 #[async_trait]
 impl SubCommandExec<DeleteRemoteError> for DeleteRemoteSubCommand {
     async fn execute(self, global_folder: &GlobalFolders) -> Result<(), DeleteRemoteError> {
+        let remote_packages = load_remote_packages(global_folder)?;
         let config_file = global_folder.make_remote_tool_config(&self.name);
         if config_file.exists() {
             fs::remove_file(config_file)?;
@@ -33,15 +40,16 @@ impl SubCommandExec<DeleteRemoteError> for DeleteRemoteSubCommand {
         if self.cascade {
             let global_state = global_folder.global_state_file();
             let mut container = get_current_state(&global_state).await?;
+            container.migrate_package_sources(&remote_packages);
+
             let mut packages_to_remove = Vec::new();
             for package in container.list_installed_packages() {
                 if package.remote_name == Some(self.name.clone()) {
-                    packages_to_remove.push(package);
+                    packages_to_remove.push(package.package_id);
                 }
             }
 
-            container.remove_packages(packages_to_remove);
-            write_state(&global_state, container).await?;
+            uninstall_packages(&packages_to_remove, global_folder).await?;
         }
         Ok(())
     }
@@ -24,7 +24,7 @@ pub enum ListRemoteError {
 impl SubCommandExec<ListRemoteError> for ListRemoteSubCommand {
     async fn execute(self, global_folder: &GlobalFolders) -> Result<(), ListRemoteError> {
         let global_state = global_folder.global_state_file();
-        let container = get_current_state(&global_state).await?;
+        let mut container = get_current_state(&global_state).await?;
 
         let remote_folder = global_folder.get_remote_config_dir();
 
@@ -43,9 +43,15 @@ impl SubCommandExec<ListRemoteError> for ListRemoteSubCommand {
             info!(target: "user", "No remote configurations exist in {}", remote_folder.display());
         }
 
+        let mut remote_packages: Vec<RemotePackage> = Default::default();
         for entry in remote_configs {
             let contents = fs::read_to_string(entry.path())?;
-            let remote_package: RemotePackage = serde_json::from_str(&contents)?;
+            remote_packages.push(serde_json::from_str(&contents)?);
+        }
+
+        container.migrate_package_sources(&remote_packages);
+
+        for remote_package in remote_packages {
             info!(target: "user", "{} is sourced from {}", remote_package.name, remote_package.repository);
             for package in &container.list_installed_packages() {
                 if package.remote_name == Some(remote_package.name.clone()) {
@@ -38,7 +38,7 @@ pub async fn handle_remote(
 ) -> Result<(), RemoteError> {
     debug!("Remote command: {:?}", remote_args);
     match remote_args {
-        RemoteSubCommand::Add(args) => args.execute(global_folder).await?,
+        RemoteSubCommand::Add(args) => (*args).execute(global_folder).await?,
         RemoteSubCommand::Delete(args) => args.execute(global_folder).await?,
         RemoteSubCommand::List(args) => args.execute(global_folder).await?,
         RemoteSubCommand::Update(args) => args.execute(global_folder).await?,
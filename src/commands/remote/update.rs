@@ -1,20 +1,25 @@
 use crate::cli::*;
 use crate::commands::SubCommandExec;
+use crate::dependencies::{install_with_dependencies, DependencyError};
 use crate::model::RemotePackage;
-use crate::package::{install_package, PackageError};
-use crate::remote::{package_needs_update, update_remote};
-use crate::state::{get_current_state, update_links, PackageDescription};
-use crate::util::GlobalFolders;
+use crate::package::PackageError;
+use crate::remote::{
+    cleanup_download, download_remote, load_remote_packages, package_needs_update, update_remote,
+};
+use crate::state::{get_current_state, update_links, write_state, PackageDescription};
+use crate::util::{get_hash_for_file, GlobalFolders};
 use async_trait::async_trait;
 use std::fs;
 use thiserror::Error;
-use tracing::{debug, info, instrument};
+use tracing::{debug, error, info, instrument};
 
 #[derive(Error, Debug)]
 pub enum UpdateRemoteError {
     #[error(transparent)]
     Package(#[from] PackageError),
     #[error(transparent)]
+    Dependency(#[from] DependencyError),
+    #[error(transparent)]
     Remote(#[from] crate::remote::RemoteError),
     #[error(transparent)]
     State(#[from] crate::state::StateError),
@@ -26,35 +31,88 @@ pub enum UpdateRemoteError {
     Unkown(#[from] anyhow::Error),
     #[error("Application has not been configured")]
     NoGlobalStateFile,
+    #[error("Unable to update {names}.")]
+    RemotesFailed { names: String },
 }
 
 #[async_trait]
 impl SubCommandExec<UpdateRemoteError> for UpdateRemoteSubCommand {
     async fn execute(self, global_folder: &GlobalFolders) -> Result<(), UpdateRemoteError> {
         debug!("Executing update");
-        let remote_folder = global_folder.get_remote_config_dir();
         let global_state = global_folder.global_state_file();
-        let container = get_current_state(&global_state).await?;
+        let remote_packages = load_remote_packages(global_folder)?;
+
+        if self.print_checksums {
+            return print_checksums(remote_packages, self.only, global_folder).await;
+        }
 
-        if !remote_folder.exists() {
-            std::fs::create_dir_all(&remote_folder)?;
+        let mut container = get_current_state(&global_state).await?;
+        if container.migrate_package_sources(&remote_packages) {
+            container = write_state(&global_state, container).await?;
         }
 
-        for entry in fs::read_dir(remote_folder)? {
-            let entry = entry?;
-            debug!("Processing remote file {:?}", entry.path());
-            let contents = fs::read_to_string(entry.path())?;
-            let remote_package: RemotePackage = serde_json::from_str(&contents)?;
+        let mut checked_remotes = Vec::new();
+        let mut failures = Vec::new();
+        for remote_package in remote_packages {
             let package_name = remote_package.name.clone();
-            let installed_package = container.describe_package(&package_name);
-            if self.only == None || self.only == Some(package_name) {
-                update_package(remote_package, installed_package, global_folder).await?;
+            if self.only.is_some() && self.only != Some(package_name.clone()) {
+                continue;
+            }
+
+            if !self.force && !container.is_remote_update_due(&remote_package) {
+                info!(target: "user", "{} was checked for updates recently, skipping. Use --force to update anyway.", package_name);
+                continue;
+            }
+
+            let installed_package = container.describe_remote_package(&package_name);
+            let result = update_package(
+                remote_package,
+                installed_package,
+                self.take_over,
+                global_folder,
+            )
+            .await;
+            match result {
+                Ok(()) => checked_remotes.push(package_name),
+                Err(e) => failures.push((package_name, e)),
+            }
+        }
+
+        let mut container = get_current_state(&global_state).await?;
+        if !checked_remotes.is_empty() {
+            let now = chrono::Utc::now();
+            for name in checked_remotes {
+                let marker = global_folder.get_update_marker(&name);
+                if marker.exists() {
+                    fs::remove_file(marker)?;
+                }
+
+                container
+                    .current_state
+                    .remote_last_checked
+                    .insert(name, now);
             }
+            container = write_state(&global_state, container).await?;
         }
 
-        let container = get_current_state(&global_state).await?;
         update_links(&container, global_folder).await?;
 
+        // The remotes that updated are saved first, so a failure doesn't make them check again
+        if failures.len() == 1 {
+            return Err(failures.remove(0).1);
+        }
+
+        if !failures.is_empty() {
+            for (name, e) in &failures {
+                error!(target: "user", "Unable to update {}: {}", name, e);
+            }
+
+            let names: Vec<String> = failures.into_iter().map(|(name, _)| name).collect();
+            return Err(UpdateRemoteError::RemotesFailed {
+                names: names.join(", "),
+            });
+        }
+
         Ok(())
     }
 }
@@ -63,24 +121,85 @@ impl SubCommandExec<UpdateRemoteError> for UpdateRemoteSubCommand {
 async fn update_package(
     remote_package: RemotePackage,
     installed_package: Option<PackageDescription>,
+    take_over: bool,
     global_folder: &GlobalFolders,
 ) -> Result<(), UpdateRemoteError> {
     info!(target: "user", "Updating {}", remote_package.name);
     debug!(remote_package=?remote_package, installed_package=?installed_package);
-    let etag = match installed_package {
-        None => None,
-        Some(pacakge) => pacakge.etag,
+    let (etag, last_modified) = match installed_package {
+        None => (None, None),
+        Some(pacakge) => (pacakge.etag, pacakge.last_modified),
     };
 
-    if package_needs_update(&remote_package, etag).await? {
+    if package_needs_update(&remote_package, etag, last_modified).await? {
         info!(target: "user", "Downloading {} from remote.", &remote_package.name);
         let artifact = update_remote(remote_package, global_folder).await?;
-        install_package(&artifact, true, global_folder).await?;
-        debug!("Removing file {:?}", artifact);
-        fs::remove_file(&artifact.path)?;
+        let package = install_with_dependencies(&artifact, true, take_over, global_folder).await;
+        cleanup_download(&artifact, global_folder)?;
+        package?;
     } else {
         info!(target: "user", "Package was already up-to-date, skipping update.");
     }
 
     Ok(())
 }
+
+/// Download every remote and print the sha256 of its archive, without checking pins or installing anything.
+async fn print_checksums(
+    remote_packages: Vec<RemotePackage>,
+    only: Option<String>,
+    global_folder: &GlobalFolders,
+) -> Result<(), UpdateRemoteError> {
+    for remote_package in remote_packages {
+        if only.is_some() && only.as_ref() != Some(&remote_package.name) {
+            continue;
+        }
+
+        info!(target: "user", "Downloading {} from remote.", &remote_package.name);
+        let artifact = download_remote(&remote_package, global_folder).await?;
+        let hash = get_hash_for_file(&artifact.path);
+        cleanup_download(&artifact, global_folder)?;
+        println!("{}  {}", hash?, remote_package.name);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+use crate::test_util::*;
+
+#[tokio::test]
+async fn failed_remotes_dont_hold_back_the_rest() {
+    use crate::model::{LocalPackageRepository, PackageRepository};
+
+    let (temp_dir, global_folder) = temp_global_folder();
+    let archive = make_test_archive(&temp_dir, "1.0.0", "foo").await;
+    let local = |path: &std::path::Path| {
+        PackageRepository::Local(LocalPackageRepository {
+            path: path.display().to_string(),
+        })
+    };
+    add_test_remote(&global_folder, &test_remote("foo", local(&archive.path)));
+    add_test_remote(
+        &global_folder,
+        &test_remote("missing", local(&temp_dir.join("missing.tar.gz"))),
+    );
+
+    let update = UpdateRemoteSubCommand {
+        only: None,
+        force: false,
+        take_over: false,
+        print_checksums: false,
+    };
+    assert!(update.execute(&global_folder).await.is_err());
+
+    let container = get_current_state(&global_folder.global_state_file())
+        .await
+        .unwrap();
+    let last_checked = &container.current_state.remote_last_checked;
+    assert!(last_checked.contains_key("foo"));
+    assert!(!last_checked.contains_key("missing"));
+    assert!(container.current_state.find_current_package("foo").is_ok());
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
@@ -0,0 +1,76 @@
+use crate::cli::UseSubCommand;
+use crate::package::{install_package, load_installed_package};
+use crate::remote::{cleanup_download, download_remote_version, find_remote_for_package};
+use crate::state::*;
+use crate::util::{split_package_spec, GlobalFolders};
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Error, Debug)]
+pub enum UseError {
+    #[error("Expected `{spec}` to be in the form `<package>@<version>`.")]
+    InvalidPackageSpec { spec: String },
+    #[error(transparent)]
+    StateError(#[from] crate::state::StateError),
+    #[error(transparent)]
+    PackageError(#[from] crate::package::PackageError),
+    #[error(transparent)]
+    RemoteError(#[from] crate::remote::RemoteError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[error(transparent)]
+    UknownError(#[from] anyhow::Error),
+}
+
+pub async fn handle_use(
+    use_args: UseSubCommand,
+    global_folder: &GlobalFolders,
+) -> Result<(), UseError> {
+    let (name, version) = match split_package_spec(&use_args.package) {
+        (name, Some(version)) if !name.is_empty() && !version.is_empty() => (name, version),
+        _ => {
+            return Err(UseError::InvalidPackageSpec {
+                spec: use_args.package.clone(),
+            })
+        }
+    };
+
+    let global_state = global_folder.global_state_file();
+    let mut container = get_current_state(&global_state).await?;
+
+    let mut version = version.to_string();
+    if let Err(e) = container.current_state.resolve_version(name, &version) {
+        let remote = match find_remote_for_package(&container, name, global_folder)? {
+            Some(remote) => remote,
+            None => return Err(e.into()),
+        };
+
+        info!(target: "user", "{}@{} is not installed, downloading it from {}", name, version, remote.name);
+        let (remote_version, artifact) =
+            download_remote_version(remote, &version, global_folder).await?;
+        install_package(&artifact, false, use_args.take_over, global_folder).await?;
+        cleanup_download(&artifact, global_folder)?;
+
+        version = remote_version;
+        container = get_current_state(&global_state).await?;
+    }
+
+    let package = container.current_state.resolve_version(name, &version)?;
+    let version = package.version.clone();
+    let install_container = load_installed_package(package)?;
+
+    container
+        .current_state
+        .make_package_current(&install_container, use_args.take_over)?;
+
+    let container = write_state(&global_state, container).await?;
+    update_links(&container, global_folder).await?;
+
+    info!(target: "user", "{}@{} is now the current version", name, version);
+
+    Ok(())
+}
+
+pub mod prelude {
+    pub use super::{handle_use, UseError};
+}
@@ -0,0 +1,179 @@
+use crate::cli::VerifySubCommand;
+use crate::model::PackageRepository;
+use crate::package::{check_installed_files, install_package, verify_archive};
+use crate::remote::{
+    cleanup_download, download_remote_version, load_remote_packages, update_remote,
+};
+use crate::state::*;
+use crate::util::{compare_versions, print_table, split_package_spec, GlobalFolders};
+use std::path::Path;
+use thiserror::Error;
+use tracing::{debug, info};
+
+#[derive(Error, Debug)]
+pub enum VerifyError {
+    #[error("{count} installed files don't match their package. Use --repair to reinstall the packages.")]
+    FilesChanged { count: usize },
+    #[error("{name}@{version} wasn't installed from a remote, so it can't be repaired.")]
+    NoRemote { name: String, version: String },
+    #[error("Unable to repair {name}@{version}, the remote may no longer serve that version.")]
+    RepairFailed { name: String, version: String },
+    #[error(transparent)]
+    State(#[from] crate::state::StateError),
+    #[error(transparent)]
+    Package(#[from] crate::package::PackageError),
+    #[error(transparent)]
+    Remote(#[from] crate::remote::RemoteError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Uknown(#[from] anyhow::Error),
+}
+
+pub async fn handle_verify(
+    verify_args: VerifySubCommand,
+    global_folder: &GlobalFolders,
+) -> Result<(), VerifyError> {
+    let global_state = global_folder.global_state_file();
+    let container = get_current_state(&global_state).await?;
+    let state = &container.current_state;
+
+    let mut packages: Vec<InstalledPackage> = match verify_args.package.as_deref() {
+        Some(spec) => match split_package_spec(spec) {
+            (name, Some(version)) => vec![state.resolve_version(name, version)?.clone()],
+            (name, None) => state
+                .installed_versions(name)
+                .into_iter()
+                .cloned()
+                .collect(),
+        },
+        None => state.installed_packages.values().cloned().collect(),
+    };
+    packages.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then_with(|| compare_versions(&a.version, &b.version))
+    });
+
+    if packages.is_empty() {
+        info!(target: "user", "No packages are installed.");
+        return Ok(());
+    }
+
+    let mut rows = Vec::new();
+    let mut damaged = Vec::new();
+    for package in packages {
+        let problems = check_installed_files(Path::new(&package.package_dir))?;
+        if problems.is_empty() {
+            debug!("{}@{} is intact", package.name, package.version);
+            continue;
+        }
+
+        for (file, problem) in problems {
+            rows.push(vec![
+                format!("{}@{}", package.name, package.version),
+                file,
+                problem.to_string(),
+            ]);
+        }
+        damaged.push(package);
+    }
+
+    if rows.is_empty() {
+        info!(target: "user", "Every installed file matches its package.");
+        return Ok(());
+    }
+
+    let count = rows.len();
+    print_table(&["PACKAGE", "FILE", "PROBLEM"], rows);
+
+    if !verify_args.repair {
+        return Err(VerifyError::FilesChanged { count });
+    }
+
+    for package in damaged {
+        repair_package(&package, global_folder).await?;
+        info!(target: "user", "Repaired {}@{}", package.name, package.version);
+    }
+
+    Ok(())
+}
+
+/// Download the package from its remote again, and reinstall it over the damaged one.
+async fn repair_package(
+    package: &InstalledPackage,
+    global_folder: &GlobalFolders,
+) -> Result<(), VerifyError> {
+    let no_remote = || VerifyError::NoRemote {
+        name: package.name.clone(),
+        version: package.version.clone(),
+    };
+    let remote_name = package.remote_name.as_ref().ok_or_else(no_remote)?;
+    let remote = load_remote_packages(global_folder)?
+        .into_iter()
+        .find(|remote| &remote.name == remote_name)
+        .ok_or_else(no_remote)?;
+
+    info!(target: "user", "Downloading {}@{} from {}", package.name, package.version, remote.name);
+    let artifact = match &remote.repository {
+        PackageRepository::Index(_) => {
+            download_remote_version(remote, &package.version, global_folder)
+                .await?
+                .1
+        }
+        _ => update_remote(remote, global_folder).await?,
+    };
+
+    // Only reinstall the same version, the remote may have moved on to a newer one.
+    let definition = verify_archive(
+        &artifact.path,
+        &artifact.trusted_keys,
+        &artifact.install_limits,
+        global_folder,
+    )
+    .await;
+    let definition = match definition {
+        Ok((definition, _)) => definition,
+        Err(e) => {
+            cleanup_download(&artifact, global_folder)?;
+            return Err(e.into());
+        }
+    };
+    if definition.name != package.name || definition.version != package.version {
+        cleanup_download(&artifact, global_folder)?;
+        debug!(
+            "Remote provided {}@{} instead",
+            definition.name, definition.version
+        );
+        return Err(VerifyError::RepairFailed {
+            name: package.name.clone(),
+            version: package.version.clone(),
+        });
+    }
+
+    let global_state = global_folder.global_state_file();
+    let previous_state = get_current_state(&global_state).await?.current_state;
+
+    let installed = install_package(&artifact, true, false, global_folder).await;
+    cleanup_download(&artifact, global_folder)?;
+    installed?;
+
+    // Installing makes the package current, so put back what was current before, including nothing.
+    let mut container = get_current_state(&global_state).await?;
+    container.current_state = previous_state;
+    let container = write_state(&global_state, container).await?;
+    update_links(&container, global_folder).await?;
+
+    if !check_installed_files(Path::new(&package.package_dir))?.is_empty() {
+        return Err(VerifyError::RepairFailed {
+            name: package.name.clone(),
+            version: package.version.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+pub mod prelude {
+    pub use super::{handle_verify, VerifyError};
+}
@@ -0,0 +1,117 @@
+use crate::cli::WhichSubCommand;
+use crate::resolve::{resolve_binary, ResolveError};
+use crate::state::*;
+use crate::util::{compare_versions, print_table, GlobalFolders};
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Error, Debug)]
+pub enum WhichError {
+    #[error("No installed package provides {name}.")]
+    NoSuchBinary { name: String },
+    #[error(transparent)]
+    State(#[from] crate::state::StateError),
+    #[error(transparent)]
+    Resolve(#[from] ResolveError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Uknown(#[from] anyhow::Error),
+}
+
+pub async fn handle_which(
+    which_args: WhichSubCommand,
+    global_folder: &GlobalFolders,
+) -> Result<(), WhichError> {
+    let global_state = global_folder.global_state_file();
+    let container = get_current_state(&global_state).await?;
+    let state = &container.current_state;
+
+    let version_override = std::env::var(crate::util::TOOLUP_VERSION_OVERRIDE).ok();
+    let resolved = resolve_binary(
+        state,
+        &which_args.binary,
+        version_override.as_deref(),
+        &std::env::current_dir()?,
+    );
+
+    if !which_args.all {
+        let resolved = resolved?;
+        let binary = resolved.binary;
+        let package = state.installed_packages.get(&binary.package_id);
+
+        println!("path:    {}", binary.path_to_exec);
+        println!("version: {}", binary.version);
+        println!(
+            "package: {}",
+            package
+                .map(|x| format!("{}@{}", x.name, x.version))
+                .unwrap_or_else(|| binary.package_id.clone())
+        );
+        println!("remote:  {}", remote_name(package));
+        println!("reason:  {}", resolved.reason);
+        return Ok(());
+    }
+
+    let mut binaries: Vec<&InstalledBinary> = state
+        .installed_binaries
+        .values()
+        .filter(|x| x.name == which_args.binary)
+        .collect();
+    if binaries.is_empty() {
+        return Err(WhichError::NoSuchBinary {
+            name: which_args.binary,
+        });
+    }
+    binaries.sort_by(|a, b| compare_versions(&b.version, &a.version));
+
+    let resolved = match resolved {
+        Ok(resolved) => Some(resolved),
+        Err(e) => {
+            info!(target: "user", "Unable to resolve {}: {}", which_args.binary, e);
+            None
+        }
+    };
+    let current_id = state
+        .current_binaries
+        .get(&which_args.binary)
+        .map(|x| &x.id);
+
+    let rows = binaries
+        .into_iter()
+        .map(|binary| {
+            let package = state.installed_packages.get(&binary.package_id);
+            let mut status = Vec::new();
+            if let Some(resolved) = resolved.as_ref().filter(|x| x.binary.id == binary.id) {
+                status.push(format!("selected ({})", resolved.reason));
+            }
+            if Some(&binary.id) == current_id {
+                status.push("current".to_string());
+            }
+
+            vec![
+                binary.version.clone(),
+                package
+                    .map(|x| x.name.clone())
+                    .unwrap_or_else(|| binary.package_id.clone()),
+                remote_name(package),
+                binary.path_to_exec.clone(),
+                status.join(", "),
+            ]
+        })
+        .collect();
+
+    print_table(&["VERSION", "PACKAGE", "REMOTE", "PATH", "STATUS"], rows);
+
+    Ok(())
+}
+
+fn remote_name(package: Option<&InstalledPackage>) -> String {
+    package
+        .and_then(|x| x.remote_name.clone())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub mod prelude {
+    pub use super::{handle_which, WhichError};
+}
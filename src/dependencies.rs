@@ -0,0 +1,404 @@
+use crate::model::{GeneratedDefinedPackage, PackageRepository};
+use crate::package::{
+    install_package, load_installed_package, read_archive_definition, read_package_definition,
+    PackageError,
+};
+use crate::remote::{
+    cleanup_download, download_remote_version, find_remote_for_package, update_remote,
+    DownloadedArtifact, RemoteError,
+};
+use crate::state::{get_current_state, write_state, InstalledPackage, InstalledState, StateError};
+use crate::util::{version_matches, GlobalFolders};
+use std::collections::BTreeMap;
+use std::path::Path;
+use thiserror::Error;
+use tracing::{debug, info};
+
+#[derive(Error, Debug)]
+pub enum DependencyError {
+    #[error("{package} needs {name} {version_req}, but it isn't installed and there isn't a remote for it. Add a remote for {name}, or install it, and try again.")]
+    NoRemote {
+        package: String,
+        name: String,
+        version_req: String,
+    },
+    #[error("{package} needs {name} {version_req}, but the remote {remote} provided {provided}.")]
+    NotSatisfied {
+        package: String,
+        name: String,
+        version_req: String,
+        remote: String,
+        provided: String,
+    },
+    #[error(
+        "{package} needs {name} {version_req}, but {name}@{version} is needed by another package."
+    )]
+    Conflict {
+        package: String,
+        name: String,
+        version_req: String,
+        version: String,
+    },
+    #[error("{package} would make {name}@{version} current, but {dependent} needs {name} {dependent_req}.")]
+    BreaksDependent {
+        package: String,
+        name: String,
+        version: String,
+        dependent: String,
+        dependent_req: String,
+    },
+    #[error(transparent)]
+    State(#[from] StateError),
+    #[error(transparent)]
+    Package(#[from] PackageError),
+    #[error(transparent)]
+    Remote(#[from] RemoteError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Read the archive's definition, install its dependencies, and then install the package itself.
+///
+/// Dependencies are resolved before the package is installed, so it never becomes current
+/// without them.
+pub async fn install_with_dependencies(
+    artifact: &DownloadedArtifact,
+    overwrite: bool,
+    take_over: bool,
+    global_folder: &GlobalFolders,
+) -> Result<GeneratedDefinedPackage, DependencyError> {
+    let package = read_archive_definition(&artifact.path, &artifact.trusted_keys)?;
+    install_dependencies(&package, take_over, global_folder).await?;
+
+    Ok(install_package(artifact, overwrite, take_over, global_folder).await?)
+}
+
+/// What has to happen for a dependency to be satisfied.
+enum DependencyStep {
+    Install(DownloadedArtifact),
+    MakeCurrent(InstalledPackage),
+}
+
+/// Make sure every dependency of `package`, and their dependencies, is installed and current.
+///
+/// A dependency is satisfied by the current version when it matches the range, or else by the
+/// highest installed version that does, which becomes current. Otherwise it's downloaded from
+/// its remote. Everything is resolved and checked before any of it is installed.
+pub async fn install_dependencies(
+    package: &GeneratedDefinedPackage,
+    take_over: bool,
+    global_folder: &GlobalFolders,
+) -> Result<(), DependencyError> {
+    let mut steps = Vec::new();
+    let result = match resolve_dependencies(package, &mut steps, global_folder).await {
+        Ok(()) => apply_steps(&steps, take_over, global_folder).await,
+        Err(e) => Err(e),
+    };
+
+    for step in &steps {
+        if let DependencyStep::Install(artifact) = step {
+            cleanup_download(artifact, global_folder)?;
+        }
+    }
+
+    result
+}
+
+async fn resolve_dependencies(
+    package: &GeneratedDefinedPackage,
+    steps: &mut Vec<DependencyStep>,
+    global_folder: &GlobalFolders,
+) -> Result<(), DependencyError> {
+    let container = get_current_state(&global_folder.global_state_file()).await?;
+    let state = &container.current_state;
+    let mut pending = dependencies_of(package);
+    let mut resolved: BTreeMap<String, String> = BTreeMap::new();
+
+    while let Some((needed_by, name, version_req)) = pending.pop() {
+        if let Some(version) = resolved.get(&name) {
+            if version_matches(version, &version_req) {
+                continue;
+            }
+
+            return Err(DependencyError::Conflict {
+                package: needed_by,
+                version: version.clone(),
+                name,
+                version_req,
+            });
+        }
+
+        if let Ok(current) = state.find_current_package(&name) {
+            if version_matches(&current.version, &version_req) {
+                debug!(
+                    "{} needs {} {}, which is satisfied by {}",
+                    needed_by, name, version_req, current.version
+                );
+                resolved.insert(name, current.version.clone());
+                continue;
+            }
+        }
+
+        let installed = state
+            .installed_versions(&name)
+            .into_iter()
+            .find(|installed| version_matches(&installed.version, &version_req));
+        if let Some(installed) = installed {
+            check_dependents(state, package, &needed_by, &name, &installed.version)?;
+            info!(target: "user", "{} needs {} {}, using the installed {}", needed_by, name, version_req, installed.version);
+            let definition = read_package_definition(Path::new(&installed.package_dir))?;
+            pending.extend(dependencies_of(&definition));
+            resolved.insert(name, installed.version.clone());
+            steps.push(DependencyStep::MakeCurrent(installed.clone()));
+            continue;
+        }
+
+        let remote = match find_remote_for_package(&container, &name, global_folder)? {
+            Some(remote) => remote,
+            None => {
+                return Err(DependencyError::NoRemote {
+                    package: needed_by,
+                    name,
+                    version_req,
+                })
+            }
+        };
+
+        info!(target: "user", "{} needs {} {}, downloading it from {}", needed_by, name, version_req, remote.name);
+        let remote_name = remote.name.clone();
+        let artifact = match &remote.repository {
+            PackageRepository::Index(_) => {
+                download_remote_version(remote, &version_req, global_folder)
+                    .await?
+                    .1
+            }
+            _ => update_remote(remote, global_folder).await?,
+        };
+
+        let definition = read_archive_definition(&artifact.path, &artifact.trusted_keys);
+        steps.push(DependencyStep::Install(artifact));
+
+        let definition = definition?;
+        if definition.name != name || !version_matches(&definition.version, &version_req) {
+            return Err(DependencyError::NotSatisfied {
+                package: needed_by,
+                name,
+                version_req,
+                remote: remote_name,
+                provided: format!("{}@{}", definition.name, definition.version),
+            });
+        }
+
+        check_dependents(state, package, &needed_by, &name, &definition.version)?;
+
+        pending.extend(dependencies_of(&definition));
+        resolved.insert(name, definition.version.clone());
+    }
+
+    Ok(())
+}
+
+/// Make sure switching the current `name` to `version` doesn't break another current package
+/// that depends on it. `package`, the one being installed, is replaced so it doesn't count.
+fn check_dependents(
+    state: &InstalledState,
+    package: &GeneratedDefinedPackage,
+    needed_by: &str,
+    name: &str,
+    version: &str,
+) -> Result<(), DependencyError> {
+    for (dependent, dependent_req) in find_dependents(state, name) {
+        let is_current = matches!(
+            state.find_current_package(&dependent.name),
+            Ok(current) if current.id == dependent.id
+        );
+        if !is_current || dependent.name == package.name || version_matches(version, &dependent_req)
+        {
+            continue;
+        }
+
+        return Err(DependencyError::BreaksDependent {
+            package: needed_by.to_string(),
+            name: name.to_string(),
+            version: version.to_string(),
+            dependent: format!("{}@{}", dependent.name, dependent.version),
+            dependent_req,
+        });
+    }
+
+    Ok(())
+}
+
+/// Apply the steps, deepest dependencies first.
+async fn apply_steps(
+    steps: &[DependencyStep],
+    take_over: bool,
+    global_folder: &GlobalFolders,
+) -> Result<(), DependencyError> {
+    let global_state = global_folder.global_state_file();
+    for step in steps.iter().rev() {
+        match step {
+            DependencyStep::Install(artifact) => {
+                install_package(artifact, false, take_over, global_folder).await?;
+            }
+            DependencyStep::MakeCurrent(package) => {
+                let mut container = get_current_state(&global_state).await?;
+                container
+                    .current_state
+                    .make_package_current(&load_installed_package(package)?, take_over)?;
+                write_state(&global_state, container).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Each dependency of `package`, as the package that needs it, the name, and the version range.
+fn dependencies_of(package: &GeneratedDefinedPackage) -> Vec<(String, String, String)> {
+    let needed_by = format!("{}@{}", package.name, package.version);
+    package
+        .dependencies
+        .iter()
+        .map(|(name, version_req)| (needed_by.clone(), name.clone(), version_req.clone()))
+        .collect()
+}
+
+/// Every installed package that depends on `name`, with the version range it needs.
+pub fn find_dependents<'a>(
+    state: &'a InstalledState,
+    name: &str,
+) -> Vec<(&'a InstalledPackage, String)> {
+    let mut dependents = Vec::new();
+    for package in state.installed_packages.values() {
+        let definition = match read_package_definition(Path::new(&package.package_dir)) {
+            Ok(definition) => definition,
+            Err(e) => {
+                debug!("Unable to read definition of {}: {:?}", package.id, e);
+                continue;
+            }
+        };
+
+        if let Some(version_req) = definition.dependencies.get(name) {
+            dependents.push((package, version_req.clone()));
+        }
+    }
+
+    dependents
+}
+
+#[cfg(test)]
+use crate::test_util::*;
+
+/// A Local remote for an archive, named `name`.
+#[cfg(test)]
+fn add_local_remote(global_folder: &GlobalFolders, name: &str, path: &Path) {
+    use crate::model::LocalPackageRepository;
+
+    let repository = PackageRepository::Local(LocalPackageRepository {
+        path: path.display().to_string(),
+    });
+    add_test_remote(global_folder, &test_remote(name, repository));
+}
+
+#[tokio::test]
+async fn dependencies_are_installed_from_remotes() {
+    let (temp_dir, global_folder) = temp_global_folder();
+    let archive = |name: &'static str, version: &'static str, dependencies: &'static str| {
+        let temp_dir = temp_dir.clone();
+        let extra_config = format!("dependencies = {{ {} }}", dependencies);
+        async move {
+            make_named_archive(
+                &temp_dir,
+                name,
+                version,
+                name,
+                &extra_config,
+                &Default::default(),
+            )
+            .await
+        }
+    };
+
+    let bar = archive("bar", "2.1.0", "").await;
+    let old_bar = archive("bar", "1.5.0", "").await;
+    let foo = archive("foo", "1.0.0", "bar = \">=2.0\"").await;
+    let quux = archive("quux", "1.0.0", "bar = \"^1\"").await;
+    let baz = archive("baz", "1.0.0", "qux = \"^1\"").await;
+
+    let global_state = global_folder.global_state_file();
+    let current_version = |name: &'static str| {
+        let global_state = global_state.clone();
+        async move {
+            let container = get_current_state(&global_state).await.unwrap();
+            container
+                .current_state
+                .find_current_package(name)
+                .ok()
+                .map(|package| package.version.clone())
+        }
+    };
+
+    let result = install_with_dependencies(&foo, false, false, &global_folder).await;
+    assert!(matches!(result, Err(DependencyError::NoRemote { .. })));
+    assert_eq!(
+        None,
+        current_version("foo").await,
+        "foo shouldn't be installed"
+    );
+
+    // An installed version that doesn't match the range isn't enough
+    install_package(&old_bar, false, false, &global_folder)
+        .await
+        .unwrap();
+    add_local_remote(&global_folder, "bar", &bar.path);
+    install_with_dependencies(&foo, false, false, &global_folder)
+        .await
+        .unwrap();
+    assert_eq!(Some("2.1.0".to_string()), current_version("bar").await);
+    assert_eq!(Some("1.0.0".to_string()), current_version("foo").await);
+    assert!(bar.path.exists(), "local archives shouldn't be deleted");
+
+    let container = get_current_state(&global_state).await.unwrap();
+    let dependents = find_dependents(&container.current_state, "bar");
+    assert_eq!(1, dependents.len());
+    assert_eq!("foo", dependents[0].0.name);
+    assert_eq!(">=2.0", dependents[0].1);
+
+    // The installed version that matches would break foo, so nothing changes
+    let result = install_with_dependencies(&quux, false, false, &global_folder).await;
+    assert!(matches!(
+        result,
+        Err(DependencyError::BreaksDependent { .. })
+    ));
+    assert_eq!(Some("2.1.0".to_string()), current_version("bar").await);
+    assert_eq!(
+        None,
+        current_version("quux").await,
+        "quux shouldn't be installed"
+    );
+
+    // Without foo, the installed version that matches is used, instead of the remote's 2.1.0
+    let foo_id = dependents[0].0.id.clone();
+    crate::package::uninstall_packages(&[foo_id], &global_folder)
+        .await
+        .unwrap();
+    install_with_dependencies(&quux, false, false, &global_folder)
+        .await
+        .unwrap();
+    assert_eq!(Some("1.5.0".to_string()), current_version("bar").await);
+    assert_eq!(Some("1.0.0".to_string()), current_version("quux").await);
+
+    // A remote that provides something other than what's needed is an error, and nothing changes
+    add_local_remote(&global_folder, "qux", &bar.path);
+    let result = install_with_dependencies(&baz, false, false, &global_folder).await;
+    assert!(matches!(result, Err(DependencyError::NotSatisfied { .. })));
+    assert_eq!(
+        None,
+        current_version("baz").await,
+        "baz shouldn't be installed"
+    );
+    assert_eq!(Some("1.5.0".to_string()), current_version("bar").await);
+
+    std::fs::remove_dir_all(temp_dir).unwrap();
+}
@@ -1,16 +1,24 @@
 mod cli;
 mod commands;
+mod dependencies;
 mod model;
 mod package;
 mod remote;
+mod resolve;
+mod signing;
 mod state;
+#[cfg(test)]
+mod test_util;
 mod util;
 
 pub mod prelude {
     pub use crate::cli::*;
     pub use crate::commands::{
-        handle_config, handle_exec, handle_package, handle_remote, print_version, CommandError,
+        handle_config, handle_conflicts, handle_exec, handle_gc, handle_package, handle_remote,
+        handle_use, handle_verify, handle_which, print_version, CommandError,
     };
+    pub use crate::remote::spawn_background_update;
+    pub use crate::resolve::resolve_binary;
     pub use crate::state::get_current_state;
-    pub use crate::util::{exec, GlobalFolders};
+    pub use crate::util::{exec, GlobalFolders, TOOLUP_VERSION_OVERRIDE};
 }
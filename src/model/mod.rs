@@ -2,11 +2,15 @@ mod package;
 mod remote;
 
 pub const GENERATED_FILE_NAME: &str = "archive.json";
+pub const SIGNATURE_FILE_NAME: &str = "archive.json.sig";
 
 pub use package::{
-    GeneratedDefinedPackage, GenericPackage, InstalledPackageContainer, UserDefinedPackage,
+    EntrypointDefinition, GeneratedDefinedPackage, GenericPackage, InstalledPackageContainer,
+    PackageSignature, PackageTarget, UserDefinedPackage,
 };
 pub use remote::{
-    AuthScript, AuthStrategy, LocalPackageRepository, PackageRepository, RemotePackage,
+    AuthScript, AuthStrategy, GitArtifact, GitPackageRepository, HttpAuthStrategy,
+    HttpPackageRepository, IndexPackageRepository, IndexedVersion, InstallLimits,
+    LocalPackageRepository, PackageIndex, PackageRepository, PackageSource, RemotePackage,
     S3PackageRepository,
 };
@@ -1,12 +1,156 @@
+use super::PackageSource;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
+/// The string in `env` values and `args` that is replaced with the directory the package is installed to.
+pub const PACKAGE_ROOT_VARIABLE: &str = "${PACKAGE_ROOT}";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserDefinedPackage<'a> {
     pub name: &'a str,
-    pub entrypoints: Vec<&'a str>,
+    pub entrypoints: Vec<EntrypointDefinition>,
     pub version: &'a str,
+    /// Environment variables set for every entrypoint.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Options for a single entrypoint, keyed by the entrypoint's command name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub entrypoint_options: BTreeMap<String, EntrypointOptions>,
+    /// The platform the package is built for. Packages without one can be installed anywhere.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<PackageTarget>,
+    /// Other toolup packages this package needs, and the version range each must match.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, String>,
+}
+
+/// The platform a package is built for.
+///
+/// Written as `<os>-<arch>[-<libc>]`, like `linux-x86_64-gnu` or `macos-aarch64`, using the names Rust uses.
+#[derive(Debug, Serialize, Clone, Deserialize, PartialEq, Eq)]
+pub struct PackageTarget {
+    pub os: String,
+    pub arch: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub libc: Option<String>,
+}
+
+impl PackageTarget {
+    /// The platform toolup is running on.
+    pub fn current() -> Self {
+        let libc = if cfg!(target_env = "gnu") {
+            Some("gnu")
+        } else if cfg!(target_env = "musl") {
+            Some("musl")
+        } else {
+            None
+        };
+
+        PackageTarget {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            libc: libc.map(|x| x.to_string()),
+        }
+    }
+
+    /// Parse a target written as `<os>-<arch>[-<libc>]`.
+    pub fn parse(target: &str) -> Option<Self> {
+        let parts: Vec<&str> = target.split('-').collect();
+        if parts.iter().any(|x| x.is_empty()) {
+            return None;
+        }
+
+        match parts.as_slice() {
+            [os, arch] => Some(PackageTarget {
+                os: os.to_string(),
+                arch: arch.to_string(),
+                libc: None,
+            }),
+            [os, arch, libc] => Some(PackageTarget {
+                os: os.to_string(),
+                arch: arch.to_string(),
+                libc: Some(libc.to_string()),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether a package built for this target can run on `host`. The libc only matters when the package names one.
+    pub fn supports(&self, host: &PackageTarget) -> bool {
+        self.os == host.os
+            && self.arch == host.arch
+            && (self.libc.is_none() || self.libc == host.libc)
+    }
+}
+
+impl std::fmt::Display for PackageTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.libc {
+            Some(libc) => write!(f, "{}-{}-{}", self.os, self.arch, libc),
+            None => write!(f, "{}-{}", self.os, self.arch),
+        }
+    }
+}
+
+/// An entrypoint in `package.toml`.
+///
+/// Either a path, which is run by its file name, or a table that names the command and can add aliases.
+///
+/// ```toml
+/// entrypoints = [
+///     "bin/bar",
+///     { name = "foo", path = "bin/foo-linux-amd64", aliases = ["f"] },
+/// ]
+/// ```
+#[derive(Debug, Serialize, Clone, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum EntrypointDefinition {
+    Path(String),
+    Named {
+        name: String,
+        path: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        aliases: Vec<String>,
+    },
+}
+
+impl EntrypointDefinition {
+    pub fn path(&self) -> &str {
+        match self {
+            EntrypointDefinition::Path(path) => path,
+            EntrypointDefinition::Named { path, .. } => path,
+        }
+    }
+
+    /// Every command name the entrypoint can be run as.
+    pub fn names(&self) -> Vec<String> {
+        match self {
+            EntrypointDefinition::Path(path) => {
+                let name = std::path::Path::new(path)
+                    .file_name()
+                    .map(|x| x.to_string_lossy().to_string())
+                    .unwrap_or_else(|| path.clone());
+                vec![name]
+            }
+            EntrypointDefinition::Named { name, aliases, .. } => {
+                let mut names = vec![name.clone()];
+                names.extend(aliases.iter().cloned());
+                names
+            }
+        }
+    }
+}
+
+/// How to run one entrypoint, on top of the package's `env`.
+#[derive(Debug, Serialize, Clone, Deserialize, Default, PartialEq, Eq)]
+pub struct EntrypointOptions {
+    /// Environment variables to set, which take precedence over the package's.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Arguments placed before the ones the user passed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Clone, Deserialize)]
@@ -16,14 +160,66 @@ pub struct GeneratedDefinedPackage {
     pub version: String,
     pub achived_at: DateTime<Utc>,
     pub file_hashes: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub entrypoint_options: BTreeMap<String, EntrypointOptions>,
+    /// Each alias, and the name of the entrypoint it runs.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<PackageTarget>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, String>,
+}
+
+impl GeneratedDefinedPackage {
+    /// The environment and leading arguments to run `command` with, when the package is installed at `package_root`.
+    ///
+    /// Aliases use the options of the entrypoint they run.
+    pub fn exec_options(
+        &self,
+        command: &str,
+        package_root: &str,
+    ) -> (BTreeMap<String, String>, Vec<String>) {
+        let interpolate = |value: &String| value.replace(PACKAGE_ROOT_VARIABLE, package_root);
+
+        let mut env: BTreeMap<String, String> = self
+            .env
+            .iter()
+            .map(|(key, value)| (key.clone(), interpolate(value)))
+            .collect();
+        let mut args = Vec::new();
+
+        let entrypoint = self.aliases.get(command).map_or(command, |x| x.as_str());
+        if let Some(options) = self.entrypoint_options.get(entrypoint) {
+            for (key, value) in &options.env {
+                env.insert(key.clone(), interpolate(value));
+            }
+            args.extend(options.args.iter().map(interpolate));
+        }
+
+        (env, args)
+    }
+}
+
+/// A detached ed25519 signature over `archive.json`, stored next to it in the archive.
+#[derive(Debug, Serialize, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageSignature {
+    /// The base64 encoded public key of the signer.
+    pub public_key: String,
+    /// The base64 encoded signature.
+    pub signature: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct InstalledPackageContainer {
     pub package: GeneratedDefinedPackage,
     pub path_to_root: String,
-    pub remote_name: Option<String>,
+    pub source: Option<PackageSource>,
     pub etag: Option<String>,
+    pub last_modified: Option<String>,
 }
 
 pub trait GenericPackage {
@@ -45,3 +241,84 @@ impl GenericPackage for GeneratedDefinedPackage {
         crate::util::make_package_id(&self.name, &self.version)
     }
 }
+
+#[test]
+fn validate_exec_options() {
+    let package = GeneratedDefinedPackage {
+        name: "foo".to_string(),
+        entrypoints: BTreeMap::from([("foo".to_string(), "bin/foo".to_string())]),
+        version: "1.0.0".to_string(),
+        achived_at: Utc::now(),
+        file_hashes: BTreeMap::new(),
+        env: BTreeMap::from([
+            ("JAVA_HOME".to_string(), "${PACKAGE_ROOT}/jre".to_string()),
+            ("MODE".to_string(), "package".to_string()),
+        ]),
+        entrypoint_options: BTreeMap::from([(
+            "foo".to_string(),
+            EntrypointOptions {
+                env: BTreeMap::from([("MODE".to_string(), "foo".to_string())]),
+                args: vec!["--data".to_string(), "${PACKAGE_ROOT}/share".to_string()],
+            },
+        )]),
+        aliases: BTreeMap::from([("f".to_string(), "foo".to_string())]),
+        target: None,
+        dependencies: Default::default(),
+    };
+
+    let (env, args) = package.exec_options("foo", "/opt/foo");
+    assert_eq!(Some(&"/opt/foo/jre".to_string()), env.get("JAVA_HOME"));
+    assert_eq!(Some(&"foo".to_string()), env.get("MODE"));
+    assert_eq!(vec!["--data", "/opt/foo/share"], args);
+
+    let (env, args) = package.exec_options("f", "/opt/foo");
+    assert_eq!(Some(&"foo".to_string()), env.get("MODE"));
+    assert_eq!(vec!["--data", "/opt/foo/share"], args);
+
+    let (env, args) = package.exec_options("bar", "/opt/foo");
+    assert_eq!(Some(&"package".to_string()), env.get("MODE"));
+    assert!(args.is_empty());
+}
+
+#[test]
+fn validate_entrypoint_definitions() {
+    let package: UserDefinedPackage = toml::from_str(
+        r#"
+name = "foo"
+version = "1.0.0"
+entrypoints = ["bin/bar", { name = "foo", path = "bin/foo-linux-amd64", aliases = ["f"] }]
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(vec!["bar"], package.entrypoints[0].names());
+    assert_eq!("bin/bar", package.entrypoints[0].path());
+    assert_eq!(vec!["foo", "f"], package.entrypoints[1].names());
+    assert_eq!("bin/foo-linux-amd64", package.entrypoints[1].path());
+}
+
+#[test]
+fn validate_package_targets() {
+    let host = PackageTarget::parse("linux-x86_64-gnu").unwrap();
+    assert_eq!("linux-x86_64-gnu", host.to_string());
+
+    assert!(PackageTarget::parse("linux-x86_64")
+        .unwrap()
+        .supports(&host));
+    assert!(PackageTarget::parse("linux-x86_64-gnu")
+        .unwrap()
+        .supports(&host));
+    assert!(!PackageTarget::parse("linux-x86_64-musl")
+        .unwrap()
+        .supports(&host));
+    assert!(!PackageTarget::parse("macos-x86_64")
+        .unwrap()
+        .supports(&host));
+    assert!(!PackageTarget::parse("linux-aarch64")
+        .unwrap()
+        .supports(&host));
+
+    assert!(PackageTarget::parse("linux").is_none());
+    assert!(PackageTarget::parse("linux--gnu").is_none());
+    assert!(PackageTarget::parse("linux-x86_64-gnu-extra").is_none());
+}
@@ -1,26 +1,68 @@
+use chrono::{DateTime, Utc};
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 
+use super::PackageTarget;
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct RemotePackage {
     pub name: String,
     pub update_period_seconds: i64,
+    /// How many versions, in addition to the current one, `toolup gc` should keep.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_versions: Option<usize>,
+    /// Public keys that packages from this remote must be signed by. When empty, unsigned packages are accepted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_keys: Vec<String>,
+    /// The sha256 the downloaded archive must have, for remotes that are kept at one version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Limits on what archives from this remote may expand to. When not set, the defaults are used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install_limits: Option<InstallLimits>,
     #[serde(flatten)]
     pub repository: PackageRepository,
 }
 
+/// Limits on what an archive may expand to, so a bad remote can't fill the disk with a decompression bomb.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct InstallLimits {
+    pub max_entries: u64,
+    pub max_total_bytes: u64,
+    pub max_compression_ratio: u64,
+}
+
+impl Default for InstallLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 100_000,
+            max_total_bytes: 16 * 1024 * 1024 * 1024,
+            max_compression_ratio: 100,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "package-repository-type", rename_all = "kebab-case")]
 pub enum PackageRepository {
     S3(S3PackageRepository),
     Local(LocalPackageRepository),
+    Http(HttpPackageRepository),
+    Git(GitPackageRepository),
+    Index(IndexPackageRepository),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct S3PackageRepository {
     pub url: String,
+    /// URLs to use instead of `url` on specific platforms, keyed by target like `linux-x86_64`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub target_urls: BTreeMap<String, String>,
     #[serde(flatten)]
     pub auth_strategy: AuthStrategy,
 }
@@ -31,11 +73,226 @@ pub struct LocalPackageRepository {
     pub path: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GitPackageRepository {
+    pub url: String,
+    /// Branch or tag to check out. When not set, the default branch is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    #[serde(flatten)]
+    pub artifact: GitArtifact,
+}
+
+/// Where the package comes from, inside of the git repo. Paths are relative to the repo root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "git-artifact", rename_all = "kebab-case")]
+pub enum GitArtifact {
+    /// Archive the package described by a `package.toml` in the repo.
+    #[serde(rename_all = "kebab-case")]
+    PackageConfig {
+        config_path: String,
+        target_dir: String,
+    },
+    /// Install a pre-built archive that is committed to the repo.
+    #[serde(rename_all = "kebab-case")]
+    Archive { archive_path: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HttpPackageRepository {
+    pub url: String,
+    /// URLs to use instead of `url` on specific platforms, keyed by target like `linux-x86_64`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub target_urls: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub auth_strategy: HttpAuthStrategy,
+}
+
+/// A remote that follows a channel or version range of an `index.json`, instead of a single artifact.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IndexPackageRepository {
+    pub url: String,
+    /// Only install versions tagged with this channel, e.g. `stable` or `beta`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// Only install versions that match this semver range, e.g. `^1.2`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_req: Option<String>,
+    #[serde(flatten)]
+    pub auth_strategy: HttpAuthStrategy,
+}
+
+/// The `index.json` served by an index remote.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageIndex {
+    pub versions: Vec<IndexedVersion>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IndexedVersion {
+    pub version: String,
+    /// Location of the archive. Relative URLs are resolved against the index's URL.
+    pub url: String,
+    pub sha256: String,
+    #[serde(default)]
+    pub channels: Vec<String>,
+    /// The platform the archive is built for, like `linux-x86_64-gnu`. A version can be listed once per target.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+impl PackageIndex {
+    /// The highest version that is in `channel` and matches `version_req`, when they are set.
+    ///
+    /// Only entries that can run on `host` are considered, preferring the one built most specifically for it.
+    pub fn latest(
+        &self,
+        channel: Option<&str>,
+        version_req: Option<&VersionReq>,
+        host: &PackageTarget,
+    ) -> Option<&IndexedVersion> {
+        self.versions
+            .iter()
+            .filter(|entry| entry.supports(host))
+            .filter(|entry| match channel {
+                Some(channel) => entry.channels.iter().any(|x| x == channel),
+                None => true,
+            })
+            .filter_map(|entry| entry.semver().map(|version| (version, entry)))
+            .filter(|(version, _)| match version_req {
+                Some(version_req) => version_req.matches(version),
+                None => true,
+            })
+            .max_by(|(left, left_entry), (right, right_entry)| {
+                left.cmp(right)
+                    .then_with(|| left_entry.specificity().cmp(&right_entry.specificity()))
+            })
+            .map(|(_, entry)| entry)
+    }
+
+    /// The version named exactly `version`, otherwise the highest version matching it as a range.
+    pub fn find_version(&self, version_req: &str, host: &PackageTarget) -> Option<&IndexedVersion> {
+        if let Some(entry) = self
+            .versions
+            .iter()
+            .filter(|x| x.version == version_req && x.supports(host))
+            .max_by_key(|x| x.specificity())
+        {
+            return Some(entry);
+        }
+
+        let version_req = crate::util::parse_version_req(version_req).ok()?;
+        self.latest(None, Some(&version_req), host)
+    }
+}
+
+impl IndexedVersion {
+    pub fn semver(&self) -> Option<Version> {
+        Version::parse(&self.version).ok()
+    }
+
+    /// Entries without a target run anywhere. Entries with a target that can't be parsed run nowhere.
+    fn supports(&self, host: &PackageTarget) -> bool {
+        match &self.target {
+            Some(target) => PackageTarget::parse(target)
+                .map(|x| x.supports(host))
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    fn specificity(&self) -> usize {
+        target_specificity(self.target.as_deref())
+    }
+}
+
+/// How closely a target names a platform, so `linux-x86_64-gnu` wins over `linux-x86_64`, which wins over nothing.
+fn target_specificity(target: Option<&str>) -> usize {
+    target
+        .and_then(PackageTarget::parse)
+        .map(|x| if x.libc.is_some() { 2 } else { 1 })
+        .unwrap_or(0)
+}
+
+/// The entry in `target_urls` for `host`, or `url` when there isn't one.
+fn url_for_target<'a>(
+    url: &'a str,
+    target_urls: &'a BTreeMap<String, String>,
+    host: &PackageTarget,
+) -> &'a str {
+    target_urls
+        .iter()
+        .filter(|(target, _)| {
+            PackageTarget::parse(target)
+                .map(|x| x.supports(host))
+                .unwrap_or(false)
+        })
+        .max_by_key(|(target, _)| target_specificity(Some(target.as_str())))
+        .map(|(_, url)| url.as_str())
+        .unwrap_or(url)
+}
+
+impl S3PackageRepository {
+    /// The URL to download from on `host`.
+    pub fn url_for(&self, host: &PackageTarget) -> &str {
+        url_for_target(&self.url, &self.target_urls, host)
+    }
+}
+
+impl HttpPackageRepository {
+    /// The URL to download from on `host`.
+    pub fn url_for(&self, host: &PackageTarget) -> &str {
+        url_for_target(&self.url, &self.target_urls, host)
+    }
+}
+
+impl PackageRepository {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PackageRepository::S3(_) => "s3",
+            PackageRepository::Local(_) => "local",
+            PackageRepository::Http(_) => "http",
+            PackageRepository::Git(_) => "git",
+            PackageRepository::Index(_) => "index",
+        }
+    }
+
+    pub fn location(&self) -> &str {
+        match self {
+            PackageRepository::S3(s3) => &s3.url,
+            PackageRepository::Local(fs) => &fs.path,
+            PackageRepository::Http(http) => &http.url,
+            PackageRepository::Git(git) => &git.url,
+            PackageRepository::Index(index) => &index.url,
+        }
+    }
+}
+
 impl fmt::Display for PackageRepository {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             PackageRepository::S3(s3) => write!(f, "S3 resources located at {}", s3.url),
             PackageRepository::Local(fs) => write!(f, "Local resources located at {}", fs.path),
+            PackageRepository::Http(http) => write!(f, "HTTP resources located at {}", http.url),
+            PackageRepository::Git(git) => match &git.reference {
+                Some(reference) => write!(f, "Git repo {} at {}", git.url, reference),
+                None => write!(f, "Git repo {}", git.url),
+            },
+            PackageRepository::Index(index) => {
+                write!(f, "Package index located at {}", index.url)?;
+                if let Some(channel) = &index.channel {
+                    write!(f, " following {}", channel)?;
+                }
+                if let Some(version_req) = &index.version_req {
+                    write!(f, " matching {}", version_req)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -48,8 +305,97 @@ pub enum AuthStrategy {
     Script(AuthScript),
 }
 
+/// How to authenticate with a HTTP remote. The script must export `TOOLUP_HTTP_TOKEN` for
+/// bearer auth, or `TOOLUP_HTTP_USERNAME` and `TOOLUP_HTTP_PASSWORD` for basic auth.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "auth-strategy", rename_all = "kebab-case")]
+pub enum HttpAuthStrategy {
+    None,
+    Bearer(AuthScript),
+    Basic(AuthScript),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct AuthScript {
     pub script_path: String,
 }
+
+/// Where an installed package came from.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageSource {
+    pub remote_name: String,
+    pub repository_kind: String,
+    pub url: String,
+    /// When the package was downloaded. Unknown for packages installed before sources were recorded.
+    pub downloaded_at: Option<DateTime<Utc>>,
+}
+
+impl PackageSource {
+    pub fn new(remote: &RemotePackage, downloaded_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            remote_name: remote.name.clone(),
+            repository_kind: remote.repository.kind().to_string(),
+            url: remote.repository.location().to_string(),
+            downloaded_at,
+        }
+    }
+}
+
+#[test]
+fn validate_target_selection() {
+    let host = PackageTarget::parse("linux-x86_64-gnu").unwrap();
+    let entry = |version: &str, target: Option<&str>| IndexedVersion {
+        version: version.to_string(),
+        url: format!("{}-{}", version, target.unwrap_or("any")),
+        sha256: String::new(),
+        channels: Vec::new(),
+        target: target.map(|x| x.to_string()),
+    };
+    let index = PackageIndex {
+        versions: vec![
+            entry("1.0.0", None),
+            entry("1.1.0", Some("linux-x86_64")),
+            entry("1.1.0", Some("linux-x86_64-gnu")),
+            entry("1.1.0", Some("macos-aarch64")),
+            entry("2.0.0", Some("linux-x86_64-musl")),
+            entry("2.0.0", Some("not a target")),
+        ],
+    };
+
+    assert_eq!(
+        "1.1.0-linux-x86_64-gnu",
+        index.latest(None, None, &host).unwrap().url
+    );
+    let mac = PackageTarget::parse("macos-aarch64").unwrap();
+    assert_eq!(
+        "1.1.0-macos-aarch64",
+        index.find_version("1.1.0", &mac).unwrap().url
+    );
+    let windows = PackageTarget::parse("windows-x86_64").unwrap();
+    assert_eq!("1.0.0-any", index.latest(None, None, &windows).unwrap().url);
+    assert!(index.find_version("2.0.0", &host).is_none());
+
+    let http = HttpPackageRepository {
+        url: "https://example.com/foo.tar.gz".to_string(),
+        target_urls: BTreeMap::from([
+            (
+                "linux-x86_64".to_string(),
+                "https://example.com/foo-linux.tar.gz".to_string(),
+            ),
+            (
+                "linux-x86_64-gnu".to_string(),
+                "https://example.com/foo-linux-gnu.tar.gz".to_string(),
+            ),
+        ]),
+        auth_strategy: HttpAuthStrategy::None,
+    };
+    assert_eq!(
+        "https://example.com/foo-linux-gnu.tar.gz",
+        http.url_for(&host)
+    );
+    let musl = PackageTarget::parse("linux-x86_64-musl").unwrap();
+    assert_eq!("https://example.com/foo-linux.tar.gz", http.url_for(&musl));
+    assert_eq!("https://example.com/foo.tar.gz", http.url_for(&windows));
+}
@@ -1,15 +1,21 @@
 use flate2::read::GzDecoder;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::{self, File};
-use std::io::prelude::*;
+use std::io::Read;
 use std::path::*;
 use tar::Archive;
 use thiserror::Error;
-use tracing::{debug, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 
-use crate::model::{GeneratedDefinedPackage, InstalledPackageContainer, GENERATED_FILE_NAME};
+use crate::model::{
+    GeneratedDefinedPackage, InstallLimits, InstalledPackageContainer, PackageSignature,
+    PackageTarget, GENERATED_FILE_NAME, SIGNATURE_FILE_NAME,
+};
 use crate::remote::DownloadedArtifact;
-use crate::state::{get_current_state, write_state};
-use crate::util::{get_hash_for_contents, set_executable, GlobalFolders};
+use crate::state::{
+    get_current_state, update_links, write_state, InstalledPackage, InstalledState,
+};
+use crate::util::{get_hash_for_file, set_executable, unique_timestamp, GlobalFolders};
 
 #[derive(Error, Debug)]
 pub enum PackageError {
@@ -23,6 +29,49 @@ pub enum PackageError {
         expected: String,
         computed: String,
     },
+    #[error("Installing {package} would replace binaries from other packages: {conflicts}. Use --take-over to replace them.")]
+    BinaryConflict { package: String, conflicts: String },
+    #[error("{name}@{version} is already installed. Use --overwrite to replace it.")]
+    AlreadyInstalled { name: String, version: String },
+    #[error("`{entry}` in `{path}` is an absolute path, which packages can't contain.")]
+    AbsolutePathInArchive { path: String, entry: String },
+    #[error("`{entry}` in `{path}` uses `..` to leave the package.")]
+    PathTraversalInArchive { path: String, entry: String },
+    #[error("`{entry}` in `{path}` links to `{target}`, which is outside of the package.")]
+    LinkOutsidePackage {
+        path: String,
+        entry: String,
+        target: String,
+    },
+    #[error(
+        "`{entry}` in `{path}` is a device or other special file, which packages can't contain."
+    )]
+    SpecialFileInArchive { path: String, entry: String },
+    #[error("`{entry}` in `{path}` is already in the package, entries can't replace each other.")]
+    DuplicateEntry { path: String, entry: String },
+    #[error("`{entry}` in `{path}` isn't listed in {}.", GENERATED_FILE_NAME)]
+    UndeclaredEntry { path: String, entry: String },
+    #[error("`{path}` isn't signed, but it's required to be signed by a trusted key.")]
+    UnsignedPackage { path: String },
+    #[error("`{path}` is signed by {key}, which isn't a trusted key.")]
+    UntrustedSignature { path: String, key: String },
+    #[error("The signature in `{path}` doesn't match its {}.", GENERATED_FILE_NAME)]
+    InvalidSignature { path: String },
+    #[error("`{path}` has more than {limit} entries, refusing to extract it.")]
+    TooManyEntries { path: String, limit: u64 },
+    #[error("`{path}` expands to more than {limit} bytes, refusing to extract it.")]
+    TooLarge { path: String, limit: u64 },
+    #[error(
+        "`{path}` expands to more than {limit} times its compressed size, refusing to extract it."
+    )]
+    CompressionRatioTooHigh { path: String, limit: u64 },
+    #[error("{name}@{version} is built for {target}, which can't run on {host}.")]
+    WrongTarget {
+        name: String,
+        version: String,
+        target: String,
+        host: String,
+    },
     #[error(transparent)]
     StateError(#[from] crate::state::StateError),
     #[error(transparent)]
@@ -33,51 +82,285 @@ pub enum PackageError {
     UknownError(#[from] anyhow::Error),
 }
 
+/// The steps of an install, reported to the install hook once each has finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InstallStep {
+    Extracted,
+    MovedPrevious,
+    Swapped,
+    StateWritten,
+}
+
+type InstallHook<'a> = &'a (dyn Fn(InstallStep) -> Result<(), PackageError> + Sync);
+
+/// Marks the dirs packages are staged in under the tool root, so `toolup gc` can find abandoned ones.
+pub const STAGING_MARKER: &str = "tmp.";
+
+/// The dir an install started at `timestamp` extracts the package into. Every call is a new dir.
+pub fn staging_dir_name(timestamp: i64) -> String {
+    format!("{}{}", STAGING_MARKER, unique_timestamp(timestamp))
+}
+
+/// The dir an install started at `timestamp` moves the previous install of the same version to.
+pub fn backup_dir_name(timestamp: i64) -> String {
+    format!("previous.{}{}", STAGING_MARKER, unique_timestamp(timestamp))
+}
+
+/// The dir `verify_archive` extracts an archive into.
+pub fn verify_dir_name(timestamp: i64) -> String {
+    format!("verify.{}{}", STAGING_MARKER, unique_timestamp(timestamp))
+}
+
+/// Install the package, and make it the current version.
+///
+/// The package is staged in a temp dir, the previous install of the same version is moved aside,
+/// and the state is written last. If any step fails, the previous install and state are restored.
+/// Returns the definition of the package that was installed.
 pub async fn install_package(
     local_artifact: &DownloadedArtifact,
     overwrite: bool,
+    take_over: bool,
     global_folder: &GlobalFolders,
-) -> Result<(), PackageError> {
+) -> Result<GeneratedDefinedPackage, PackageError> {
+    install_package_with_hook(local_artifact, overwrite, take_over, global_folder, &|_| {
+        Ok(())
+    })
+    .await
+}
+
+async fn install_package_with_hook(
+    local_artifact: &DownloadedArtifact,
+    overwrite: bool,
+    take_over: bool,
+    global_folder: &GlobalFolders,
+    hook: InstallHook<'_>,
+) -> Result<GeneratedDefinedPackage, PackageError> {
     debug!("Installing package");
     let tool_root_dir = global_folder.tool_root_dir.clone();
     let tool_root_dir = Path::new(&tool_root_dir);
-    let tmp_extract_dir = tool_root_dir.join(format!("tmp.{}", chrono::Utc::now().timestamp()));
-    let package_def = extract_and_validate(&local_artifact.path, &tmp_extract_dir).await?;
+    let now = chrono::Utc::now().timestamp();
+    let mut transaction = InstallTransaction {
+        staging_dir: tool_root_dir.join(staging_dir_name(now)),
+        backup_dir: tool_root_dir.join(backup_dir_name(now)),
+        dest: None,
+        moved_previous: false,
+        swapped: false,
+        previous_state: None,
+    };
 
-    debug!("Package definition {:?}", package_def);
+    let global_state = global_folder.global_state_file();
+    let result = transaction
+        .run(local_artifact, overwrite, take_over, &global_state, hook)
+        .await;
 
-    let real_path =
-        move_package_to_correct_location(&tmp_extract_dir, tool_root_dir, &package_def, overwrite)
-            .await?;
+    match result {
+        Ok(package) => {
+            transaction.commit();
+            Ok(package)
+        }
+        Err(e) => {
+            transaction.rollback(global_folder).await;
+            Err(e)
+        }
+    }
+}
 
-    let global_state = global_folder.global_state_file();
-    let mut container = get_current_state(&global_state).await?;
-    let install_container = InstalledPackageContainer {
-        package: package_def,
-        path_to_root: real_path,
-        remote_name: None,
-        etag: local_artifact.etag.clone(),
-    };
+/// Tracks what an install has changed, so that it can be undone.
+struct InstallTransaction {
+    staging_dir: PathBuf,
+    backup_dir: PathBuf,
+    dest: Option<PathBuf>,
+    moved_previous: bool,
+    swapped: bool,
+    previous_state: Option<InstalledState>,
+}
 
-    debug!("Installed package is {:?}", install_container);
+impl InstallTransaction {
+    async fn run(
+        &mut self,
+        local_artifact: &DownloadedArtifact,
+        overwrite: bool,
+        take_over: bool,
+        global_state: &Path,
+        hook: InstallHook<'_>,
+    ) -> Result<GeneratedDefinedPackage, PackageError> {
+        let (package_def, _) = extract_and_validate(
+            &local_artifact.path,
+            &self.staging_dir,
+            &local_artifact.install_limits,
+            &local_artifact.trusted_keys,
+        )
+        .await?;
+        debug!("Package definition {:?}", package_def);
+        if let Some(target) = &package_def.target {
+            let host = PackageTarget::current();
+            if !target.supports(&host) {
+                return Err(PackageError::WrongTarget {
+                    name: package_def.name,
+                    version: package_def.version,
+                    target: target.to_string(),
+                    host: host.to_string(),
+                });
+            }
+        }
+        hook(InstallStep::Extracted)?;
 
-    container
-        .current_state
-        .add_installed_package(&install_container);
-    container
-        .current_state
-        .make_package_current(&install_container)?;
+        let mut container = get_current_state(global_state).await?;
+        let conflicts = container.current_state.find_conflicts(&package_def);
+        if !conflicts.is_empty() && !take_over {
+            let conflicts: Vec<String> = conflicts
+                .iter()
+                .map(|(binary, owner)| format!("{} (from {})", binary, owner))
+                .collect();
+            return Err(PackageError::BinaryConflict {
+                package: package_def.name,
+                conflicts: conflicts.join(", "),
+            });
+        }
 
-    write_state(&global_state, container).await?;
+        let unix_friendly_name = package_def.name.replace(' ', "_");
+        let tool_root = self
+            .staging_dir
+            .parent()
+            .expect("Staging dir to have a parent");
+        let dest = tool_root
+            .join("packages")
+            .join(&unix_friendly_name)
+            .join(&package_def.version);
+        self.dest = Some(dest.clone());
 
-    Ok(())
+        if dest.exists() {
+            if !overwrite {
+                return Err(PackageError::AlreadyInstalled {
+                    name: package_def.name,
+                    version: package_def.version,
+                });
+            }
+
+            info!(target: "user", "Moving aside old install of {}", package_def.name);
+            fs::rename(&dest, &self.backup_dir)?;
+            self.moved_previous = true;
+        }
+        hook(InstallStep::MovedPrevious)?;
+
+        info!(target: "user", "Installing {} at {}", package_def.name, dest.display().to_string());
+        fs::create_dir_all(dest.parent().expect("Partent to exist"))?;
+        fs::rename(&self.staging_dir, &dest)?;
+        self.swapped = true;
+        hook(InstallStep::Swapped)?;
+
+        let real_path = fs::canonicalize(&dest)?;
+        let install_container = InstalledPackageContainer {
+            package: package_def.clone(),
+            path_to_root: real_path.display().to_string(),
+            source: local_artifact.source.clone(),
+            etag: local_artifact.etag.clone(),
+            last_modified: local_artifact.last_modified.clone(),
+        };
+
+        debug!("Installed package is {:?}", install_container);
+
+        let previous_state = container.current_state.clone();
+        container
+            .current_state
+            .add_installed_package(&install_container);
+        container
+            .current_state
+            .make_package_current(&install_container, take_over)?;
+
+        write_state(global_state, container).await?;
+        self.previous_state = Some(previous_state);
+        hook(InstallStep::StateWritten)?;
+
+        Ok(package_def)
+    }
+
+    /// The install succeeded, so the previous install is no longer needed.
+    fn commit(self) {
+        if self.moved_previous {
+            if let Err(e) = fs::remove_dir_all(&self.backup_dir) {
+                warn!(
+                    "Unable to remove {:?}, it will be removed by gc. {:?}",
+                    self.backup_dir, e
+                );
+            }
+        }
+    }
+
+    /// Undo every step that was completed. This is best effort, failures are logged.
+    async fn rollback(self, global_folder: &GlobalFolders) {
+        info!(target: "user", "Install failed, rolling back");
+
+        if let Some(previous_state) = self.previous_state {
+            let global_state = global_folder.global_state_file();
+            let result = match get_current_state(&global_state).await {
+                Ok(mut container) => {
+                    container.current_state = previous_state;
+                    match write_state(&global_state, container).await {
+                        Ok(container) => update_links(&container, global_folder).await,
+                        Err(e) => Err(e),
+                    }
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                error!(target: "user", "Unable to restore the previous state: {}", e);
+            }
+        }
+
+        if let Some(dest) = &self.dest {
+            if self.swapped {
+                if let Err(e) = fs::remove_dir_all(dest) {
+                    error!(target: "user", "Unable to remove {}: {}", dest.display(), e);
+                }
+            }
+
+            if self.moved_previous {
+                if let Err(e) = fs::rename(&self.backup_dir, dest) {
+                    error!(target: "user", "Unable to restore {} from {}: {}", dest.display(), self.backup_dir.display(), e);
+                }
+            }
+        }
+
+        if self.staging_dir.exists() {
+            if let Err(e) = fs::remove_dir_all(&self.staging_dir) {
+                warn!(
+                    "Unable to remove {:?}, it will be removed by gc. {:?}",
+                    self.staging_dir, e
+                );
+            }
+        }
+    }
 }
 
-#[instrument(skip(temp_dir))]
+/// Extract the archive into a temp dir, and check it the same way an install would, without installing it.
+///
+/// Returns the package definition, and the public key it was signed with if it's signed.
+pub async fn verify_archive(
+    package_file: &Path,
+    trusted_keys: &[String],
+    limits: &InstallLimits,
+    global_folder: &GlobalFolders,
+) -> Result<(GeneratedDefinedPackage, Option<String>), PackageError> {
+    let temp_dir = Path::new(&global_folder.tool_root_dir)
+        .join(verify_dir_name(chrono::Utc::now().timestamp()));
+
+    let result = extract_and_validate(package_file, &temp_dir, limits, trusted_keys).await;
+
+    if temp_dir.exists() {
+        fs::remove_dir_all(&temp_dir)?;
+    }
+
+    result
+}
+
+#[instrument(skip(temp_dir, limits, trusted_keys))]
 async fn extract_and_validate(
     package_file: &Path,
     temp_dir: &Path,
-) -> Result<GeneratedDefinedPackage, PackageError> {
+    limits: &InstallLimits,
+    trusted_keys: &[String],
+) -> Result<(GeneratedDefinedPackage, Option<String>), PackageError> {
     let file = match fs::File::open(&package_file) {
         Ok(file) => file,
         Err(e) => {
@@ -88,35 +371,180 @@ async fn extract_and_validate(
         }
     };
 
-    let mut gz: Vec<u8> = Vec::new();
-
-    let mut d = GzDecoder::new(file);
-    if let Err(e) = d.read_to_end(&mut gz) {
-        return Err(PackageError::UnableToExtractPackage {
-            path: package_file.display().to_string(),
-            error: e,
-        });
-    }
-
     debug!(
         "Temp dir to extract archive to {}",
         temp_dir.display().to_string()
     );
 
-    let mut a = Archive::new(gz.as_slice());
-    if let Err(e) = a.unpack(&temp_dir) {
-        return Err(PackageError::UnableToExtractPackage {
+    let extract_error = |error: std::io::Error| PackageError::UnableToExtractPackage {
+        path: package_file.display().to_string(),
+        error,
+    };
+
+    let compressed_size = file.metadata()?.len();
+    let max_total_bytes = limits
+        .max_total_bytes
+        .min(compressed_size.saturating_mul(limits.max_compression_ratio));
+
+    // Each file is hashed right after it's extracted, so the archive is only read once and never held in memory.
+    fs::create_dir_all(temp_dir)?;
+    let package_root = fs::canonicalize(temp_dir)?;
+    let mut archive = Archive::new(GzDecoder::new(file));
+    archive.set_overwrite(false);
+    let mut computed_hashes = BTreeMap::new();
+    let mut extracted_entries = BTreeSet::new();
+    let mut symlinks = Vec::new();
+    let mut entry_count: u64 = 0;
+    let mut total_size: u64 = 0;
+    for entry in archive.entries().map_err(extract_error)? {
+        let mut entry = entry.map_err(extract_error)?;
+        let entry_path = entry.path().map_err(extract_error)?.into_owned();
+
+        // The header says how much data follows it, so the limits are checked before anything is written.
+        entry_count += 1;
+        if entry_count > limits.max_entries {
+            return Err(PackageError::TooManyEntries {
+                path: package_file.display().to_string(),
+                limit: limits.max_entries,
+            });
+        }
+
+        total_size = total_size.saturating_add(entry.size());
+        if total_size > max_total_bytes {
+            let path = package_file.display().to_string();
+            return Err(if max_total_bytes == limits.max_total_bytes {
+                PackageError::TooLarge {
+                    path,
+                    limit: limits.max_total_bytes,
+                }
+            } else {
+                PackageError::CompressionRatioTooHigh {
+                    path,
+                    limit: limits.max_compression_ratio,
+                }
+            });
+        }
+
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_pax_global_extensions() {
+            continue;
+        }
+
+        let entry_name = validate_entry_path(package_file, &entry_path)?;
+        let parent = prepare_parent(package_file, &package_root, &entry_name)?;
+        let dest = parent.join(entry_name.file_name().unwrap_or_default());
+        let existing = fs::symlink_metadata(&dest).ok();
+        let duplicate_entry = || PackageError::DuplicateEntry {
             path: package_file.display().to_string(),
-            error: e,
-        });
+            entry: entry_name.display().to_string(),
+        };
+
+        if entry_type.is_dir() {
+            if existing.map(|x| !x.is_dir()).unwrap_or(false) {
+                return Err(duplicate_entry());
+            }
+            entry.unpack_in(&package_root).map_err(extract_error)?;
+            continue;
+        }
+
+        // Nothing may replace an earlier entry, otherwise a file could be written through a symlink.
+        if existing.is_some() {
+            return Err(duplicate_entry());
+        }
+
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let target = entry
+                .link_name()
+                .map_err(extract_error)?
+                .map(|x| x.into_owned())
+                .unwrap_or_default();
+
+            // Symlinks are relative to the directory they're in, hard links to the root of the archive.
+            let base = match entry_type.is_symlink() {
+                true => &parent,
+                false => &package_root,
+            };
+            if resolve_link_target(&package_root, base, &target).is_none() {
+                return Err(PackageError::LinkOutsidePackage {
+                    path: package_file.display().to_string(),
+                    entry: entry_name.display().to_string(),
+                    target: target.display().to_string(),
+                });
+            }
+
+            entry.unpack_in(&package_root).map_err(extract_error)?;
+            if entry_type.is_symlink() {
+                symlinks.push(entry_name.clone());
+            }
+            extracted_entries.insert(entry_name.display().to_string());
+            continue;
+        }
+
+        if !entry_type.is_file() {
+            return Err(PackageError::SpecialFileInArchive {
+                path: package_file.display().to_string(),
+                entry: entry_name.display().to_string(),
+            });
+        }
+
+        // tar does the write, so its own checks against writing outside of the package still apply.
+        if !entry.unpack_in(&package_root).map_err(extract_error)? {
+            return Err(PackageError::PathTraversalInArchive {
+                path: package_file.display().to_string(),
+                entry: entry_name.display().to_string(),
+            });
+        }
+        computed_hashes.insert(entry_name.display().to_string(), get_hash_for_file(&dest)?);
+        extracted_entries.insert(entry_name.display().to_string());
+    }
+
+    // Links can be chained through each other, so only check where they resolve to once they all exist.
+    for symlink in symlinks {
+        if let Ok(resolved) = fs::canonicalize(package_root.join(&symlink)) {
+            if !resolved.starts_with(&package_root) {
+                return Err(PackageError::LinkOutsidePackage {
+                    path: package_file.display().to_string(),
+                    entry: symlink.display().to_string(),
+                    target: resolved.display().to_string(),
+                });
+            }
+        }
+    }
+
+    let definition = fs::read(temp_dir.join(GENERATED_FILE_NAME))?;
+    let signature = match fs::read(temp_dir.join(SIGNATURE_FILE_NAME)) {
+        Ok(signature) => Some(signature),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let signed_by = check_signature(package_file, &definition, signature, trusted_keys)?;
+    let archive_def: GeneratedDefinedPackage = serde_json::from_slice(&definition)?;
+
+    for entry in extracted_entries {
+        if entry != GENERATED_FILE_NAME
+            && entry != SIGNATURE_FILE_NAME
+            && !archive_def.file_hashes.contains_key(&entry)
+        {
+            return Err(PackageError::UndeclaredEntry {
+                path: package_file.display().to_string(),
+                entry,
+            });
+        }
     }
 
-    let package_def_file = temp_dir.join(GENERATED_FILE_NAME);
-    let archive_def: GeneratedDefinedPackage =
-        serde_json::from_reader(File::open(package_def_file)?)?;
+    for (filename, expected) in &archive_def.file_hashes {
+        let computed = match computed_hashes.remove(filename) {
+            Some(computed) => computed,
+            None => get_hash_for_file(&temp_dir.join(filename))?,
+        };
 
-    for (filename, hash) in &archive_def.file_hashes {
-        valdiate_file(temp_dir.join(filename), hash).await?;
+        if *expected != computed {
+            return Err(PackageError::CurruptedArchive {
+                filename: temp_dir.join(filename).display().to_string(),
+                expected: expected.to_string(),
+                computed,
+            });
+        }
     }
 
     debug!("Package {:?} is valid, installing", &package_file);
@@ -125,51 +553,846 @@ async fn extract_and_validate(
         set_executable(&temp_dir.join(rel_path));
     }
 
-    Ok(archive_def)
+    Ok((archive_def, signed_by))
 }
 
-async fn move_package_to_correct_location(
-    temp_dir: &Path,
-    tool_root: &Path,
-    package: &GeneratedDefinedPackage,
-    overwrite: bool,
-) -> Result<String, PackageError> {
-    let unix_friendly_name = package.name.replace(' ', "_");
-    let real_dest = tool_root
-        .to_owned()
-        .join("packages")
-        .join(&unix_friendly_name)
-        .join(&package.version);
+/// Read the package definition from an archive, without extracting anything to disk.
+///
+/// The signature is checked the same way an install checks it, but the files aren't, so this is
+/// only enough to decide what to install. `install_package` still validates the whole archive.
+pub fn read_archive_definition(
+    package_file: &Path,
+    trusted_keys: &[String],
+) -> Result<GeneratedDefinedPackage, PackageError> {
+    let file = match File::open(package_file) {
+        Ok(file) => file,
+        Err(e) => {
+            return Err(PackageError::UnableToReadPackage {
+                path: package_file.display().to_string(),
+                error: e,
+            })
+        }
+    };
 
-    if real_dest.exists() && overwrite {
-        info!(target: "user", "Cleading up old install of {}", package.name);
-        fs::remove_dir_all(&real_dest)?;
-    }
+    let extract_error = |error: std::io::Error| PackageError::UnableToExtractPackage {
+        path: package_file.display().to_string(),
+        error,
+    };
 
-    info!(target: "user", "Installing {} at {}", package.name, real_dest.display().to_string());
+    let mut definition = None;
+    let mut signature = None;
+    let mut archive = Archive::new(GzDecoder::new(file));
+    for entry in archive.entries().map_err(extract_error)? {
+        let mut entry = entry.map_err(extract_error)?;
+        let entry_path = entry.path().map_err(extract_error)?.into_owned();
+        let contents = match validate_entry_path(package_file, &entry_path)? {
+            name if name == Path::new(GENERATED_FILE_NAME) => &mut definition,
+            name if name == Path::new(SIGNATURE_FILE_NAME) => &mut signature,
+            _ => continue,
+        };
 
-    fs::create_dir_all(real_dest.parent().expect("Partent to exist"))?;
-    fs::rename(temp_dir, &real_dest)?;
+        let mut buffer = Vec::new();
+        entry.read_to_end(&mut buffer).map_err(extract_error)?;
+        *contents = Some(buffer);
+    }
 
-    let real_dest = std::fs::canonicalize(real_dest).expect("Path that was written to be valid");
+    let definition = match definition {
+        Some(definition) => definition,
+        None => {
+            return Err(extract_error(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} is missing", GENERATED_FILE_NAME),
+            )))
+        }
+    };
 
-    Ok(real_dest.display().to_string())
+    check_signature(package_file, &definition, signature, trusted_keys)?;
+    Ok(serde_json::from_slice(&definition)?)
 }
 
-#[instrument]
-async fn valdiate_file(path: PathBuf, expected_checksum: &str) -> Result<(), PackageError> {
-    debug!("Validating file");
-    let mut file = File::open(&path)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
+/// Check the signature of a package's `archive.json`, returning the key it was signed with.
+///
+/// A signature that's present is always checked. When there are trusted keys, the package must be signed by one of them.
+fn check_signature(
+    package_file: &Path,
+    definition: &[u8],
+    signature: Option<Vec<u8>>,
+    trusted_keys: &[String],
+) -> Result<Option<String>, PackageError> {
+    let signature = match signature {
+        Some(signature) => signature,
+        None => {
+            return match trusted_keys.is_empty() {
+                true => Ok(None),
+                false => Err(PackageError::UnsignedPackage {
+                    path: package_file.display().to_string(),
+                }),
+            }
+        }
+    };
+
+    let signature: PackageSignature = serde_json::from_slice(&signature)?;
+    if crate::signing::verify(definition, &signature).is_err() {
+        return Err(PackageError::InvalidSignature {
+            path: package_file.display().to_string(),
+        });
+    }
 
-    let computed_hash = get_hash_for_contents(buffer.clone());
-    if expected_checksum != computed_hash {
-        return Err(PackageError::CurruptedArchive {
-            filename: path.display().to_string(),
-            expected: expected_checksum.to_string(),
-            computed: computed_hash,
+    if !trusted_keys.is_empty()
+        && !trusted_keys
+            .iter()
+            .any(|x| x.trim() == signature.public_key)
+    {
+        return Err(PackageError::UntrustedSignature {
+            path: package_file.display().to_string(),
+            key: signature.public_key,
         });
     }
+
+    Ok(Some(signature.public_key))
+}
+
+/// Normalize the path of an archive entry, rejecting any that could be written outside of the package.
+fn validate_entry_path(package_file: &Path, entry: &Path) -> Result<PathBuf, PackageError> {
+    let mut normalized = PathBuf::new();
+    for component in entry.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                return Err(PackageError::PathTraversalInArchive {
+                    path: package_file.display().to_string(),
+                    entry: entry.display().to_string(),
+                })
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(PackageError::AbsolutePathInArchive {
+                    path: package_file.display().to_string(),
+                    entry: entry.display().to_string(),
+                })
+            }
+        }
+    }
+
+    Ok(normalized)
+}
+
+/// Create the directories an entry is written into, returning the real path of its parent.
+///
+/// Earlier entries may have made any of those directories a symlink, so each one that exists is
+/// resolved on disk and must still be inside the package.
+fn prepare_parent(
+    package_file: &Path,
+    package_root: &Path,
+    entry: &Path,
+) -> Result<PathBuf, PackageError> {
+    let mut parent = package_root.to_path_buf();
+    for component in entry.parent().into_iter().flat_map(|x| x.components()) {
+        parent.push(component);
+        match fs::symlink_metadata(&parent) {
+            Ok(_) => parent = fs::canonicalize(&parent)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => fs::create_dir(&parent)?,
+            Err(e) => return Err(e.into()),
+        }
+
+        if !parent.starts_with(package_root) {
+            return Err(PackageError::LinkOutsidePackage {
+                path: package_file.display().to_string(),
+                entry: entry.display().to_string(),
+                target: parent.display().to_string(),
+            });
+        }
+    }
+
+    Ok(parent)
+}
+
+/// Resolve `target` from `base`, following the links that are already on disk.
+///
+/// Returns `None` if the target leaves the package at any point.
+fn resolve_link_target(package_root: &Path, base: &Path, target: &Path) -> Option<PathBuf> {
+    let mut resolved = base.to_path_buf();
+    for component in target.components() {
+        match component {
+            Component::Normal(part) => {
+                resolved.push(part);
+                if fs::symlink_metadata(&resolved).is_ok() {
+                    resolved = fs::canonicalize(&resolved).ok()?;
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+
+        if !resolved.starts_with(package_root) {
+            return None;
+        }
+    }
+
+    Some(resolved)
+}
+
+/// Remove the packages from the global state, and delete their install directories.
+///
+/// When the current version of a package is removed, the highest remaining version
+/// of that package becomes current.
+pub async fn uninstall_packages(
+    package_ids: &[String],
+    global_folder: &GlobalFolders,
+) -> Result<(), PackageError> {
+    let global_state = global_folder.global_state_file();
+    let mut container = get_current_state(&global_state).await?;
+    let state = &mut container.current_state;
+
+    let mut dirs_to_remove = Vec::new();
+    let mut removed_current = BTreeSet::new();
+
+    for id in package_ids {
+        let package = match state.installed_packages.get(id) {
+            Some(package) => package.clone(),
+            None => continue,
+        };
+
+        if state.current_packages.get(&package.name).map(|x| &x.id) == Some(id) {
+            removed_current.insert(package.name.clone());
+        }
+
+        info!(target: "user", "Removing {}@{}", package.name, package.version);
+        state.remove_package_by_id(id);
+        dirs_to_remove.push(package.package_dir);
+    }
+
+    for name in removed_current {
+        let fallback = state.installed_versions(&name).into_iter().next().cloned();
+
+        if let Some(fallback) = fallback {
+            info!(target: "user", "Making {}@{} the current version", fallback.name, fallback.version);
+            state.make_package_current(&load_installed_package(&fallback)?, false)?;
+        }
+    }
+
+    // Binaries that only the removed packages linked go to another package that provides them
+    state.relink_orphaned_binaries();
+
+    let container = write_state(&global_state, container).await?;
+
+    for dir in dirs_to_remove {
+        let package_dir = Path::new(&dir);
+        if package_dir.exists() {
+            debug!("Deleting {:?}", package_dir);
+            fs::remove_dir_all(package_dir)?;
+        }
+
+        if let Some(parent) = package_dir.parent() {
+            if parent.exists() && fs::read_dir(parent)?.next().is_none() {
+                debug!("Deleting empty dir {:?}", parent);
+                fs::remove_dir(parent)?;
+            }
+        }
+    }
+
+    update_links(&container, global_folder).await?;
+
     Ok(())
 }
+
+/// Rebuild the container for a package that was already installed, from its `archive.json`.
+pub fn load_installed_package(
+    package: &InstalledPackage,
+) -> Result<InstalledPackageContainer, PackageError> {
+    Ok(InstalledPackageContainer {
+        package: read_package_definition(Path::new(&package.package_dir))?,
+        path_to_root: package.package_dir.clone(),
+        source: package.source.clone(),
+        etag: package.etag.clone(),
+        last_modified: package.last_modified.clone(),
+    })
+}
+
+/// Something wrong with a file in an installed package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileProblem {
+    /// Listed in `archive.json`, but not on disk.
+    Missing,
+    /// On disk, but its hash doesn't match `archive.json`.
+    Modified,
+    /// On disk, but not listed in `archive.json`.
+    Extra,
+}
+
+impl std::fmt::Display for FileProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileProblem::Missing => write!(f, "missing"),
+            FileProblem::Modified => write!(f, "modified"),
+            FileProblem::Extra => write!(f, "extra"),
+        }
+    }
+}
+
+/// Re-hash every file in an installed package against its `archive.json`.
+///
+/// Returns each file that doesn't match, relative to the package dir.
+pub fn check_installed_files(
+    package_dir: &Path,
+) -> Result<BTreeMap<String, FileProblem>, PackageError> {
+    let definition = read_package_definition(package_dir)?;
+    let mut problems = BTreeMap::new();
+
+    for (filename, expected) in &definition.file_hashes {
+        let path = package_dir.join(filename);
+        if !path.is_file() {
+            problems.insert(filename.clone(), FileProblem::Missing);
+        } else if get_hash_for_file(&path)? != *expected {
+            problems.insert(filename.clone(), FileProblem::Modified);
+        }
+    }
+
+    for entry in walkdir::WalkDir::new(package_dir) {
+        let entry = entry.map_err(|e| PackageError::IoError(e.into()))?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+
+        let filename = entry
+            .path()
+            .strip_prefix(package_dir)
+            .expect("Base path to be well known")
+            .display()
+            .to_string();
+        if filename != GENERATED_FILE_NAME
+            && filename != SIGNATURE_FILE_NAME
+            && !definition.file_hashes.contains_key(&filename)
+        {
+            problems.insert(filename, FileProblem::Extra);
+        }
+    }
+
+    Ok(problems)
+}
+
+pub fn read_package_definition(
+    package_dir: &Path,
+) -> Result<GeneratedDefinedPackage, PackageError> {
+    let package_def_file = package_dir.join(GENERATED_FILE_NAME);
+    Ok(serde_json::from_reader(File::open(package_def_file)?)?)
+}
+
+#[cfg(test)]
+use crate::test_util::*;
+
+/// Build an archive by hand, so that it can contain entries `archive_package` would never write.
+///
+/// Each entry is a path, type, and either the contents or the link target. Only `foo` is declared.
+#[cfg(test)]
+fn make_crafted_archive(temp_dir: &Path, entries: &[(&str, tar::EntryType, &str)]) -> PathBuf {
+    use flate2::{write::GzEncoder, Compression};
+
+    fs::create_dir_all(temp_dir).unwrap();
+    let path = temp_dir.join(format!("crafted-{}.tar.gz", uuid::Uuid::new_v4()));
+    let encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+
+    let mut append = |name: &str, entry_type: tar::EntryType, data: &str| {
+        let mut header = tar::Header::new_gnu();
+        // Write the name directly, `set_path` refuses the paths these tests need.
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            header.set_link_name(data).unwrap();
+            header.set_size(0);
+            header.set_cksum();
+            builder.append(&header, std::io::empty()).unwrap();
+        } else {
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data.as_bytes()).unwrap();
+        }
+    };
+
+    append("foo", tar::EntryType::Regular, "foo");
+    for (name, entry_type, data) in entries {
+        append(name, *entry_type, data);
+    }
+
+    let mut reader = crate::util::HashingReader::new("foo".as_bytes());
+    std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+    let definition = GeneratedDefinedPackage {
+        name: "foo".to_string(),
+        entrypoints: BTreeMap::new(),
+        version: "1.0.0".to_string(),
+        achived_at: chrono::Utc::now(),
+        file_hashes: BTreeMap::from([("foo".to_string(), reader.hash())]),
+        env: Default::default(),
+        entrypoint_options: Default::default(),
+        aliases: Default::default(),
+        target: None,
+        dependencies: Default::default(),
+    };
+    let definition = serde_json::to_string(&definition).unwrap();
+    append(GENERATED_FILE_NAME, tar::EntryType::Regular, &definition);
+
+    builder.into_inner().unwrap().finish().unwrap();
+    path
+}
+
+#[tokio::test]
+async fn extract_rejects_malicious_archives() {
+    use tar::EntryType::{Char, Link, Regular, Symlink};
+
+    let temp_dir = std::env::temp_dir().join(format!("toolup-{}", uuid::Uuid::new_v4()));
+    let extract = |entries: &[(&str, tar::EntryType, &str)]| {
+        let path = make_crafted_archive(&temp_dir, entries);
+        let staging_dir = temp_dir.join(format!("tmp.{}", uuid::Uuid::new_v4()));
+        async move { extract_and_validate(&path, &staging_dir, &InstallLimits::default(), &[]).await }
+    };
+
+    assert!(extract(&[]).await.is_ok());
+
+    let result = extract(&[("/tmp/evil", Regular, "evil")]).await;
+    assert!(matches!(
+        result,
+        Err(PackageError::AbsolutePathInArchive { .. })
+    ));
+
+    let result = extract(&[("bin/../../evil", Regular, "evil")]).await;
+    assert!(matches!(
+        result,
+        Err(PackageError::PathTraversalInArchive { .. })
+    ));
+
+    for link in [
+        ("evil", Symlink, "/etc"),
+        ("bin/evil", Symlink, "../../etc"),
+        ("evil", Link, "../evil"),
+    ] {
+        let result = extract(&[link]).await;
+        assert!(
+            matches!(result, Err(PackageError::LinkOutsidePackage { .. })),
+            "{:?} should be rejected",
+            link
+        );
+    }
+
+    // Each link stays in the package on its own, but together they point at the parent dir.
+    let result = extract(&[("here", Symlink, "."), ("evil", Symlink, "here/..")]).await;
+    assert!(matches!(
+        result,
+        Err(PackageError::LinkOutsidePackage { .. })
+    ));
+
+    let result = extract(&[
+        ("here", Symlink, "."),
+        ("up", Symlink, "here/.."),
+        ("up/evil", Regular, "evil"),
+    ])
+    .await;
+    assert!(matches!(
+        result,
+        Err(PackageError::LinkOutsidePackage { .. })
+    ));
+
+    // Later entries can't replace earlier ones, or write through a link an earlier entry made.
+    for entries in [
+        vec![("foo", Regular, "replaced")],
+        vec![("evil", Symlink, "foo"), ("evil", Regular, "evil")],
+        vec![
+            ("dir", Symlink, "foo"),
+            ("dir", tar::EntryType::Directory, ""),
+        ],
+    ] {
+        let result = extract(&entries).await;
+        assert!(
+            matches!(result, Err(PackageError::DuplicateEntry { .. })),
+            "{:?} should be rejected",
+            entries
+        );
+    }
+
+    // Link targets are resolved through the links that are already on disk.
+    for entries in [
+        vec![
+            ("here", Symlink, "."),
+            ("evil", Symlink, "here/../victim"),
+            ("evil", Regular, "evil"),
+        ],
+        vec![
+            ("bin/here", Symlink, "."),
+            ("bin/evil", Symlink, "here/../../victim"),
+        ],
+        vec![("here", Symlink, "."), ("evil", Link, "here/../victim")],
+    ] {
+        let result = extract(&entries).await;
+        assert!(
+            matches!(result, Err(PackageError::LinkOutsidePackage { .. })),
+            "{:?} should be rejected",
+            entries
+        );
+    }
+    assert!(!temp_dir.join("victim").exists());
+
+    let result = extract(&[("null", Char, "")]).await;
+    assert!(matches!(
+        result,
+        Err(PackageError::SpecialFileInArchive { .. })
+    ));
+
+    let result = extract(&[("extra", Regular, "extra")]).await;
+    assert!(
+        matches!(result, Err(PackageError::UndeclaredEntry { ref entry, .. }) if entry == "extra")
+    );
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
+
+#[tokio::test]
+async fn signed_packages_need_a_trusted_key() {
+    let (temp_dir, global_folder) = temp_global_folder();
+    let (secret_key, public_key) = crate::signing::generate_key();
+    let (_, other_key) = crate::signing::generate_key();
+    fs::write(temp_dir.join("secret.key"), secret_key).unwrap();
+
+    let signed = make_test_archive_with(
+        &temp_dir,
+        "1.0.0",
+        "signed",
+        &crate::commands::ArchiveOptions {
+            sign_key: Some(temp_dir.join("secret.key")),
+            ..Default::default()
+        },
+    )
+    .await;
+    let unsigned = make_test_archive(&temp_dir, "1.0.0", "unsigned").await;
+
+    let (_, signed_by) = verify_archive(
+        &signed.path,
+        std::slice::from_ref(&public_key),
+        &Default::default(),
+        &global_folder,
+    )
+    .await
+    .unwrap();
+    assert_eq!(signed_by, Some(public_key.clone()));
+
+    let (_, signed_by) = verify_archive(&unsigned.path, &[], &Default::default(), &global_folder)
+        .await
+        .unwrap();
+    assert_eq!(signed_by, None);
+
+    let result = verify_archive(
+        &unsigned.path,
+        std::slice::from_ref(&public_key),
+        &Default::default(),
+        &global_folder,
+    )
+    .await;
+    assert!(matches!(result, Err(PackageError::UnsignedPackage { .. })));
+
+    let result = verify_archive(
+        &signed.path,
+        std::slice::from_ref(&other_key),
+        &Default::default(),
+        &global_folder,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(PackageError::UntrustedSignature { .. })
+    ));
+
+    let definition =
+        read_archive_definition(&signed.path, std::slice::from_ref(&public_key)).unwrap();
+    assert_eq!(definition.version, "1.0.0");
+    let result = read_archive_definition(&unsigned.path, &[public_key]);
+    assert!(matches!(result, Err(PackageError::UnsignedPackage { .. })));
+    let result = read_archive_definition(&signed.path, &[other_key]);
+    assert!(matches!(
+        result,
+        Err(PackageError::UntrustedSignature { .. })
+    ));
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
+
+#[tokio::test]
+async fn install_refuses_other_targets() {
+    use crate::model::PackageTarget;
+
+    let (temp_dir, global_folder) = temp_global_folder();
+    let archive_for = |version: &'static str, target: PackageTarget| {
+        let temp_dir = temp_dir.clone();
+        async move {
+            make_test_archive_with(
+                &temp_dir,
+                version,
+                "foo",
+                &crate::commands::ArchiveOptions {
+                    target: Some(target),
+                    ..Default::default()
+                },
+            )
+            .await
+        }
+    };
+
+    let other = archive_for("1.0.0", PackageTarget::parse("plan9-mips").unwrap()).await;
+    assert!(other.path.ends_with("foo-1.0.0-plan9-mips.tar.gz"));
+    let result = install_package(&other, false, false, &global_folder).await;
+    assert!(matches!(result, Err(PackageError::WrongTarget { .. })));
+    assert!(!temp_dir.join("packages/foo/1.0.0").exists());
+
+    let host = archive_for("1.0.0", PackageTarget::current()).await;
+    install_package(&host, false, false, &global_folder)
+        .await
+        .unwrap();
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
+
+#[tokio::test]
+async fn extract_enforces_install_limits() {
+    let temp_dir = std::env::temp_dir().join(format!("toolup-{}", uuid::Uuid::new_v4()));
+    // Random hex only compresses about 2:1, so it stays under the default ratio.
+    let contents: String = (0..2000)
+        .map(|_| uuid::Uuid::new_v4().simple().to_string())
+        .collect();
+    let artifact = make_test_archive(&temp_dir, "1.0.0", &contents).await;
+    let extract = |limits: InstallLimits| {
+        let staging_dir = temp_dir.join(format!("tmp.{}", uuid::Uuid::new_v4()));
+        let path = artifact.path.clone();
+        async move { extract_and_validate(&path, &staging_dir, &limits, &[]).await }
+    };
+
+    assert!(extract(InstallLimits::default()).await.is_ok());
+
+    let result = extract(InstallLimits {
+        max_entries: 1,
+        ..Default::default()
+    })
+    .await;
+    assert!(matches!(result, Err(PackageError::TooManyEntries { .. })));
+
+    let result = extract(InstallLimits {
+        max_total_bytes: 1024,
+        ..Default::default()
+    })
+    .await;
+    assert!(matches!(result, Err(PackageError::TooLarge { .. })));
+
+    let result = extract(InstallLimits {
+        max_compression_ratio: 1,
+        ..Default::default()
+    })
+    .await;
+    assert!(matches!(
+        result,
+        Err(PackageError::CompressionRatioTooHigh { .. })
+    ));
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
+
+#[tokio::test]
+async fn install_uses_the_remotes_limits() {
+    use crate::model::{LocalPackageRepository, PackageRepository, RemotePackage};
+    use crate::remote::update_remote;
+
+    let (temp_dir, global_folder) = temp_global_folder();
+    // A MiB of the same character compresses far past the default ratio.
+    let archive = make_test_archive(&temp_dir, "1.0.0", &"0".repeat(1024 * 1024)).await;
+    let remote = |install_limits: Option<InstallLimits>| RemotePackage {
+        install_limits,
+        ..test_remote(
+            "foo",
+            PackageRepository::Local(LocalPackageRepository {
+                path: archive.path.display().to_string(),
+            }),
+        )
+    };
+
+    let artifact = update_remote(remote(None), &global_folder).await.unwrap();
+    let result = install_package(&artifact, false, false, &global_folder).await;
+    assert!(matches!(
+        result,
+        Err(PackageError::CompressionRatioTooHigh { .. })
+    ));
+
+    let raised = InstallLimits {
+        max_compression_ratio: 10_000,
+        ..Default::default()
+    };
+    let artifact = update_remote(remote(Some(raised)), &global_folder)
+        .await
+        .unwrap();
+    install_package(&artifact, false, false, &global_folder)
+        .await
+        .unwrap();
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
+
+#[tokio::test]
+async fn check_installed_files_finds_changes() {
+    let (temp_dir, global_folder) = temp_global_folder();
+    let package_dir = temp_dir.join("packages/foo/1.0.0");
+
+    let artifact = make_test_archive(&temp_dir, "1.0.0", "original").await;
+    install_package(&artifact, false, false, &global_folder)
+        .await
+        .unwrap();
+    assert!(check_installed_files(&package_dir).unwrap().is_empty());
+
+    fs::write(package_dir.join("foo"), "changed").unwrap();
+    fs::write(package_dir.join("extra"), "extra").unwrap();
+    fs::remove_file(package_dir.join("package.toml")).unwrap();
+
+    let problems = check_installed_files(&package_dir).unwrap();
+    assert_eq!(
+        BTreeMap::from([
+            ("extra".to_string(), FileProblem::Extra),
+            ("foo".to_string(), FileProblem::Modified),
+            ("package.toml".to_string(), FileProblem::Missing),
+        ]),
+        problems
+    );
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
+
+#[tokio::test]
+async fn failed_installs_are_rolled_back() {
+    let (temp_dir, global_folder) = temp_global_folder();
+    let global_state = global_folder.global_state_file();
+    let installed_file = temp_dir.join("packages/foo/1.0.0/foo");
+
+    let original = make_test_archive(&temp_dir, "1.0.0", "original").await;
+    install_package(&original, false, false, &global_folder)
+        .await
+        .unwrap();
+    let original_state = get_current_state(&global_state).await.unwrap();
+
+    let replacement = make_test_archive(&temp_dir, "1.0.0", "replacement").await;
+    let steps = [
+        InstallStep::Extracted,
+        InstallStep::MovedPrevious,
+        InstallStep::Swapped,
+        InstallStep::StateWritten,
+    ];
+    for failing_step in steps {
+        let hook = move |step| {
+            if step == failing_step {
+                Err(anyhow::anyhow!("Injected failure at {:?}", step).into())
+            } else {
+                Ok(())
+            }
+        };
+        let result =
+            install_package_with_hook(&replacement, true, false, &global_folder, &hook).await;
+        assert!(result.is_err(), "{:?} should fail", failing_step);
+
+        assert_eq!(
+            "original",
+            fs::read_to_string(&installed_file).unwrap(),
+            "{:?} should restore the previous install",
+            failing_step
+        );
+        let state = get_current_state(&global_state).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(&original_state.current_state).unwrap(),
+            serde_json::to_value(&state.current_state).unwrap(),
+            "{:?} should restore the previous state",
+            failing_step
+        );
+
+        let leftovers: Vec<_> = fs::read_dir(&temp_dir)
+            .unwrap()
+            .map(|x| x.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|x| x.contains("tmp."))
+            .collect();
+        assert!(
+            leftovers.is_empty(),
+            "{:?} left {:?}",
+            failing_step,
+            leftovers
+        );
+    }
+
+    // A new version that fails is removed completely
+    let new_version = make_test_archive(&temp_dir, "2.0.0", "new").await;
+    let hook = |step| {
+        if step == InstallStep::StateWritten {
+            Err(anyhow::anyhow!("Injected failure").into())
+        } else {
+            Ok(())
+        }
+    };
+    assert!(
+        install_package_with_hook(&new_version, false, false, &global_folder, &hook)
+            .await
+            .is_err()
+    );
+    assert!(!temp_dir.join("packages/foo/2.0.0").exists());
+    let state = get_current_state(&global_state).await.unwrap();
+    assert_eq!("1.0.0", state.current_state.current_packages["foo"].version);
+    assert!(
+        global_folder.get_link_dir().join("foo").is_symlink(),
+        "the links should match the restored state"
+    );
+
+    install_package(&replacement, true, false, &global_folder)
+        .await
+        .unwrap();
+    assert_eq!("replacement", fs::read_to_string(&installed_file).unwrap());
+
+    assert!(matches!(
+        install_package(&replacement, false, false, &global_folder).await,
+        Err(PackageError::AlreadyInstalled { .. })
+    ));
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
+
+#[tokio::test]
+async fn uninstalling_current_falls_back() {
+    let (temp_dir, global_folder) = temp_global_folder();
+    let global_state = global_folder.global_state_file();
+    let link = global_folder.get_link_dir().join("foo");
+
+    for version in ["1.0.0", "2.0.0"] {
+        let archive = make_test_archive(&temp_dir, version, version).await;
+        install_package(&archive, false, false, &global_folder)
+            .await
+            .unwrap();
+    }
+    let container = get_current_state(&global_state).await.unwrap();
+    update_links(&container, &global_folder).await.unwrap();
+    let current = container.current_state.find_current_package("foo").unwrap();
+    assert_eq!("2.0.0", current.version);
+
+    let current = vec![current.id.clone()];
+    uninstall_packages(&current, &global_folder).await.unwrap();
+    let container = get_current_state(&global_state).await.unwrap();
+    let state = &container.current_state;
+    assert_eq!("1.0.0", state.current_packages.get("foo").unwrap().version);
+    let binary = state.get_current_binary_path("foo").unwrap();
+    assert_eq!("1.0.0", fs::read_to_string(binary).unwrap());
+    assert!(link.is_symlink(), "the link should still exist");
+    assert!(!temp_dir.join("packages/foo/2.0.0").exists());
+
+    // Removing the last version removes the link too
+    let last = state.current_packages.get("foo").unwrap().id.clone();
+    uninstall_packages(&[last], &global_folder).await.unwrap();
+    let container = get_current_state(&global_state).await.unwrap();
+    let state = &container.current_state;
+    assert!(state.current_packages.is_empty());
+    assert!(state.installed_packages.is_empty());
+    assert!(state.get_current_binary_path("foo").is_err());
+    assert!(!link.is_symlink(), "the link should be removed");
+    assert!(!temp_dir.join("packages/foo").exists());
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
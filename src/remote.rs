@@ -1,9 +1,23 @@
+use crate::commands::{archive_package, ArchiveOptions, ArchivePackageError};
 use crate::model::{
-    AuthStrategy, LocalPackageRepository, PackageRepository, RemotePackage, S3PackageRepository,
+    AuthStrategy, GitArtifact, GitPackageRepository, HttpAuthStrategy, HttpPackageRepository,
+    IndexPackageRepository, IndexedVersion, InstallLimits, LocalPackageRepository, PackageIndex,
+    PackageRepository, PackageSource, PackageTarget, RemotePackage, S3PackageRepository,
+};
+use crate::state::StateContainer;
+use crate::util::{
+    extract_env_from_script, get_hash_for_file, parse_version_req, spawn_detached,
+    unique_timestamp, GlobalFolders, HashingWriter, TOOLUP_DISABLE_AUTO_UPDATE,
 };
-use crate::util::{extract_env_from_script, GlobalFolders};
 use async_trait::async_trait;
-use reqwest::header::ETAG;
+use git2::build::CheckoutBuilder;
+use git2::{
+    Cred, CredentialType, Direction, FetchOptions, Oid, RemoteCallbacks, RemoteHead, Repository,
+};
+use reqwest::header::{
+    HeaderMap, HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::{Method, RequestBuilder, StatusCode};
 use rusoto_core::region::Region;
 use rusoto_core::signature::SignedRequest;
 use rusoto_credential::ChainProvider;
@@ -11,13 +25,49 @@ use rusoto_credential::ProvideAwsCredentials;
 use std::collections::BTreeMap;
 use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 use tracing::{debug, info};
 use url::Url;
 
+/// How many times git may ask for credentials on one connection before giving up.
+const MAX_GIT_CREDENTIAL_ATTEMPTS: u32 = 3;
+
+/// Seconds to wait before the shim will start another background update of the same remote.
+const BACKGROUND_UPDATE_BACKOFF_SECONDS: u64 = 10 * 60;
+
+pub const TOOLUP_HTTP_TOKEN: &str = "TOOLUP_HTTP_TOKEN";
+pub const TOOLUP_HTTP_USERNAME: &str = "TOOLUP_HTTP_USERNAME";
+pub const TOOLUP_HTTP_PASSWORD: &str = "TOOLUP_HTTP_PASSWORD";
+
 #[derive(Error, Debug)]
 pub enum RemoteError {
+    #[error("The auth script {script} did not export {name}.")]
+    MissingAuthValue { script: String, name: String },
+    #[error("Unable to find {reference} in the git repo {url}.")]
+    GitReferenceNotFound { url: String, reference: String },
+    #[error("No version in {url} matches the remote's channel and version range for {target}.")]
+    NoMatchingVersion { url: String, target: String },
+    #[error("Version {version} for {target} is not listed in {url}.")]
+    VersionNotInIndex {
+        url: String,
+        version: String,
+        target: String,
+    },
+    #[error("{name} is not a package index, so only the latest version can be installed from it.")]
+    VersionsNotSupported { name: String },
+    #[error("Expected {url} to have a sha256 of {expected}, but it was {actual}.")]
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    #[error(transparent)]
+    Semver(#[from] semver::Error),
+    #[error(transparent)]
+    Git(#[from] git2::Error),
+    #[error(transparent)]
+    Archive(#[from] ArchivePackageError),
     #[error(transparent)]
     State(#[from] crate::state::StateError),
     #[error(transparent)]
@@ -27,6 +77,8 @@ pub enum RemoteError {
     #[error(transparent)]
     Reqwest(#[from] reqwest::Error),
     #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error(transparent)]
     Uknown(#[from] anyhow::Error),
@@ -36,6 +88,12 @@ pub enum RemoteError {
 pub struct DownloadedArtifact {
     pub path: PathBuf,
     pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub source: Option<PackageSource>,
+    /// Public keys the package must be signed by, from the remote it was downloaded from.
+    pub trusted_keys: Vec<String>,
+    /// What the archive may expand to, from the remote it was downloaded from.
+    pub install_limits: InstallLimits,
 }
 
 #[async_trait]
@@ -50,6 +108,7 @@ pub trait RemoteDownload {
         &self,
         remote: &RemotePackage,
         etag: Option<String>,
+        last_modified: Option<String>,
     ) -> Result<bool, RemoteError>;
 }
 
@@ -59,6 +118,7 @@ impl RemoteDownload for S3PackageRepository {
         &self,
         _remote: &RemotePackage,
         etag: Option<String>,
+        _last_modified: Option<String>,
     ) -> Result<bool, RemoteError> {
         let etag_string = match etag {
             None => {
@@ -96,42 +156,89 @@ impl RemoteDownload for S3PackageRepository {
     ) -> Result<DownloadedArtifact, RemoteError> {
         let signed_url = self.make_presigned_url("GET").await?;
         let response = reqwest::get(signed_url).await?;
-        let headers = response.headers();
-        let etag: Option<String> = match headers.get(ETAG) {
-            Some(value) => match value.to_str() {
-                Ok(value) => Some(String::from(value)),
-                Err(_) => None,
-            },
-            None => None,
-        };
-        let bytes = response.bytes().await?;
+        save_response(response, remote, global_folder).await
+    }
+}
 
-        let now = chrono::Utc::now();
-        let mut path = global_folder.get_remote_download_dir();
+fn header_value(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
 
-        if !path.exists() {
-            fs::create_dir_all(path.clone())?;
-        }
+/// Write the body of the response into the remote download dir.
+async fn save_response(
+    response: reqwest::Response,
+    remote: &RemotePackage,
+    global_folder: &GlobalFolders,
+) -> Result<DownloadedArtifact, RemoteError> {
+    let etag = header_value(response.headers(), ETAG);
+    let last_modified = header_value(response.headers(), LAST_MODIFIED);
+    let path = new_download_path(remote, global_folder)?;
+    write_response_body(response, &path).await?;
 
-        path.push(format!("{}.download.{}", remote.name, now.timestamp()));
-        let mut file = File::create(&path)?;
-        file.write_all(&bytes)?;
+    Ok(DownloadedArtifact {
+        path,
+        etag,
+        last_modified,
+        source: None,
+        trusted_keys: Vec::new(),
+        install_limits: InstallLimits::default(),
+    })
+}
 
-        info!("Artifact saved to {}", path.display().to_string());
-        Ok(DownloadedArtifact {
-            path: path.clone(),
-            etag,
-        })
+/// Stream the body of the response to `path`, returning the sha256 of the body.
+async fn write_response_body(
+    mut response: reqwest::Response,
+    path: &Path,
+) -> Result<String, RemoteError> {
+    let mut file = HashingWriter::new(File::create(path)?);
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk)?;
+    }
+    file.flush()?;
+
+    info!("Artifact saved to {}", path.display().to_string());
+    Ok(file.hash())
+}
+
+fn new_download_path(
+    remote: &RemotePackage,
+    global_folder: &GlobalFolders,
+) -> Result<PathBuf, RemoteError> {
+    let now = chrono::Utc::now();
+    let mut path = global_folder.get_remote_download_dir();
+
+    if !path.exists() {
+        fs::create_dir_all(path.clone())?;
     }
+
+    path.push(download_file_name(&remote.name, now.timestamp()));
+    Ok(path)
+}
+
+/// Marks archives in the download dir, so `toolup gc` can find abandoned downloads.
+pub const DOWNLOAD_MARKER: &str = ".download.";
+
+/// The file a download of `remote_name` started at `timestamp` is written to.
+pub fn download_file_name(remote_name: &str, timestamp: i64) -> String {
+    format!(
+        "{}{}{}",
+        remote_name,
+        DOWNLOAD_MARKER,
+        unique_timestamp(timestamp)
+    )
 }
 
 impl S3PackageRepository {
     async fn make_presigned_url(&self, method: &str) -> Result<String, RemoteError> {
+        let target_url = self.url_for(&PackageTarget::current());
         let extra_env = match &self.auth_strategy {
             AuthStrategy::Script(auth_script) => extract_env_from_script(auth_script)?,
             AuthStrategy::DefaultAwsAuth => BTreeMap::default(),
             AuthStrategy::None => {
-                return Ok(self.url.clone());
+                return Ok(target_url.to_string());
             }
         };
 
@@ -143,9 +250,9 @@ impl S3PackageRepository {
             std::env::set_var(name, value);
         }
 
-        info!("Downloading {}", self.url);
+        info!("Downloading {}", target_url);
 
-        let url = Url::parse(&self.url)?;
+        let url = Url::parse(target_url)?;
         let domain = url.domain().expect("URL to have a domain name").to_string();
         let region = Region::Custom {
             name: "custom-domain".to_owned(),
@@ -175,6 +282,7 @@ impl RemoteDownload for LocalPackageRepository {
         &self,
         _remote: &RemotePackage,
         _etag: Option<String>,
+        _last_modified: Option<String>,
     ) -> Result<bool, RemoteError> {
         Ok(true)
     }
@@ -187,26 +295,866 @@ impl RemoteDownload for LocalPackageRepository {
         let path = PathBuf::from(&self.path);
 
         info!("Artifact from to {}", self.path);
-        Ok(DownloadedArtifact { path, etag: None })
+        Ok(DownloadedArtifact {
+            path,
+            etag: None,
+            last_modified: None,
+            source: None,
+            trusted_keys: Vec::new(),
+            install_limits: InstallLimits::default(),
+        })
     }
 }
 
-pub async fn update_remote(
+#[async_trait]
+impl RemoteDownload for HttpPackageRepository {
+    async fn needs_update(
+        &self,
+        _remote: &RemotePackage,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    ) -> Result<bool, RemoteError> {
+        if etag.is_none() && last_modified.is_none() {
+            debug!("No previous etag or last-modified value, forcing update");
+            return Ok(true);
+        }
+
+        let mut request = self.make_request(Method::HEAD)?;
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let response = request.send().await?;
+        debug!("Head response {:?}", response);
+
+        Ok(response.status() != StatusCode::NOT_MODIFIED)
+    }
+
+    async fn download(
+        &self,
+        remote: &RemotePackage,
+        global_folder: &GlobalFolders,
+    ) -> Result<DownloadedArtifact, RemoteError> {
+        info!("Downloading {}", self.url_for(&PackageTarget::current()));
+        let response = self
+            .make_request(Method::GET)?
+            .send()
+            .await?
+            .error_for_status()?;
+        save_response(response, remote, global_folder).await
+    }
+}
+
+impl HttpPackageRepository {
+    fn make_request(&self, method: Method) -> Result<RequestBuilder, RemoteError> {
+        let request = reqwest::Client::builder()
+            .build()?
+            .request(method, self.url_for(&PackageTarget::current()));
+
+        let request = match &self.auth_strategy {
+            HttpAuthStrategy::None => request,
+            HttpAuthStrategy::Bearer(auth_script) => {
+                let extra_env = extract_env_from_script(auth_script)?;
+                request.bearer_auth(require_auth_value(
+                    &extra_env,
+                    auth_script,
+                    TOOLUP_HTTP_TOKEN,
+                )?)
+            }
+            HttpAuthStrategy::Basic(auth_script) => {
+                let extra_env = extract_env_from_script(auth_script)?;
+                request.basic_auth(
+                    require_auth_value(&extra_env, auth_script, TOOLUP_HTTP_USERNAME)?,
+                    extra_env.get(TOOLUP_HTTP_PASSWORD),
+                )
+            }
+        };
+
+        Ok(request)
+    }
+}
+
+fn require_auth_value<'a>(
+    extra_env: &'a BTreeMap<String, String>,
+    auth_script: &crate::model::AuthScript,
+    name: &str,
+) -> Result<&'a String, RemoteError> {
+    extra_env
+        .get(name)
+        .ok_or_else(|| RemoteError::MissingAuthValue {
+            script: auth_script.script_path.clone(),
+            name: name.to_string(),
+        })
+}
+
+#[async_trait]
+impl RemoteDownload for GitPackageRepository {
+    async fn needs_update(
+        &self,
+        _remote: &RemotePackage,
+        etag: Option<String>,
+        _last_modified: Option<String>,
+    ) -> Result<bool, RemoteError> {
+        let etag_string = match etag {
+            None => {
+                debug!("No previous commit, forcing update");
+                return Ok(true);
+            }
+            Some(value) => value,
+        };
+
+        let (reference, commit) = self.resolve_reference()?;
+        debug!(
+            "{} is at {}, installed from {}",
+            reference, commit, etag_string
+        );
+
+        Ok(commit.to_string() != etag_string)
+    }
+
+    async fn download(
+        &self,
+        remote: &RemotePackage,
+        global_folder: &GlobalFolders,
+    ) -> Result<DownloadedArtifact, RemoteError> {
+        let download_dir = global_folder.get_remote_download_dir();
+        let checkout_dir = download_dir.join(format!("{}.git", remote.name));
+        let commit = self.checkout(&checkout_dir)?;
+
+        let path = match &self.artifact {
+            GitArtifact::Archive { archive_path } => {
+                let path = new_download_path(remote, global_folder)?;
+                fs::copy(checkout_dir.join(archive_path), &path)?;
+                path
+            }
+            GitArtifact::PackageConfig {
+                config_path,
+                target_dir,
+            } => {
+                // The repo's history can't be fixed, so don't reject versions that aren't semver.
+                archive_package(
+                    &checkout_dir.join(config_path).display().to_string(),
+                    &checkout_dir.join(target_dir).display().to_string(),
+                    &download_dir.display().to_string(),
+                    &ArchiveOptions {
+                        allow_non_semver: true,
+                        ..Default::default()
+                    },
+                )
+                .await?
+            }
+        };
+
+        info!("Artifact saved to {}", path.display().to_string());
+        Ok(DownloadedArtifact {
+            path,
+            etag: Some(commit),
+            last_modified: None,
+            source: None,
+            trusted_keys: Vec::new(),
+            install_limits: InstallLimits::default(),
+        })
+    }
+}
+
+impl GitPackageRepository {
+    /// Find the full name of the reference to check out, and the commit it points to.
+    fn resolve_reference(&self) -> Result<(String, Oid), RemoteError> {
+        let mut remote = git2::Remote::create_detached(self.url.as_str())?;
+        let connection = remote.connect_auth(Direction::Fetch, Some(git_callbacks()), None)?;
+        self.find_reference(connection.list()?)
+    }
+
+    /// Find the reference to check out in what the remote advertised.
+    fn find_reference(&self, heads: &[RemoteHead<'_>]) -> Result<(String, Oid), RemoteError> {
+        let candidates = match &self.reference {
+            None => vec!["HEAD".to_string()],
+            Some(reference) if reference.starts_with("refs/") => vec![reference.clone()],
+            Some(reference) => vec![
+                format!("refs/heads/{}", reference),
+                format!("refs/tags/{}", reference),
+            ],
+        };
+
+        for candidate in candidates {
+            // Annotated tags are listed twice, the peeled entry points at the commit.
+            let peeled = format!("{}^{{}}", candidate);
+            let head = heads
+                .iter()
+                .find(|head| head.name() == peeled)
+                .or_else(|| heads.iter().find(|head| head.name() == candidate));
+
+            if let Some(head) = head {
+                return Ok((candidate, head.oid()));
+            }
+        }
+
+        Err(RemoteError::GitReferenceNotFound {
+            url: self.url.clone(),
+            reference: self.reference.clone().unwrap_or_else(|| "HEAD".to_string()),
+        })
+    }
+
+    /// Clone or fetch the repo into `checkout_dir`, returning the commit that was checked out.
+    fn checkout(&self, checkout_dir: &Path) -> Result<String, RemoteError> {
+        let repo = if checkout_dir.exists() {
+            Repository::open(checkout_dir)?
+        } else {
+            Repository::init(checkout_dir)?
+        };
+
+        // The reference is resolved and fetched over the same connection.
+        let mut remote = repo.remote_anonymous(&self.url)?;
+        let mut connection = remote.connect_auth(Direction::Fetch, Some(git_callbacks()), None)?;
+        let (reference, _) = self.find_reference(connection.list()?)?;
+
+        info!("Fetching {} from {}", reference, self.url);
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(git_callbacks());
+        connection
+            .remote()
+            .fetch(&[&reference], Some(&mut fetch_options), None)?;
+        drop(connection);
+
+        let commit = repo.find_reference("FETCH_HEAD")?.peel_to_commit()?;
+        repo.checkout_tree(
+            commit.as_object(),
+            Some(CheckoutBuilder::new().force().remove_untracked(true)),
+        )?;
+        repo.set_head_detached(commit.id())?;
+
+        debug!("Checked out {} at {}", self.url, commit.id());
+        Ok(commit.id().to_string())
+    }
+}
+
+/// Use the ssh-agent or git credential helpers, the same way git would.
+///
+/// libgit2 asks again every time a credential is rejected, so this gives up after a few attempts
+/// instead of offering the same one forever.
+fn git_callbacks<'a>() -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    let mut attempts = 0;
+    callbacks.credentials(move |url, username, allowed| {
+        attempts += 1;
+        if attempts > MAX_GIT_CREDENTIAL_ATTEMPTS {
+            return Err(git2::Error::from_str(&format!(
+                "Authentication to {} failed after {} attempts",
+                url, MAX_GIT_CREDENTIAL_ATTEMPTS
+            )));
+        }
+
+        if allowed.contains(CredentialType::SSH_KEY) {
+            return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+        }
+
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            let config = git2::Config::open_default()?;
+            return Cred::credential_helper(&config, url, username);
+        }
+
+        Cred::default()
+    });
+    callbacks
+}
+
+#[async_trait]
+impl RemoteDownload for IndexPackageRepository {
+    async fn needs_update(
+        &self,
+        _remote: &RemotePackage,
+        etag: Option<String>,
+        _last_modified: Option<String>,
+    ) -> Result<bool, RemoteError> {
+        let index = self.fetch_index().await?;
+        let entry = self.latest(&index)?;
+        debug!("Index resolved to {} ({})", entry.version, entry.sha256);
+
+        Ok(etag.as_deref() != Some(entry.sha256.as_str()))
+    }
+
+    async fn download(
+        &self,
+        remote: &RemotePackage,
+        global_folder: &GlobalFolders,
+    ) -> Result<DownloadedArtifact, RemoteError> {
+        let index = self.fetch_index().await?;
+        let entry = self.latest(&index)?;
+        self.download_entry(entry, remote, global_folder).await
+    }
+}
+
+impl IndexPackageRepository {
+    pub async fn fetch_index(&self) -> Result<PackageIndex, RemoteError> {
+        let body = self.fetch(&Url::parse(&self.url)?).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    fn latest<'a>(&self, index: &'a PackageIndex) -> Result<&'a IndexedVersion, RemoteError> {
+        let version_req = match &self.version_req {
+            Some(version_req) => Some(parse_version_req(version_req)?),
+            None => None,
+        };
+
+        let host = PackageTarget::current();
+        index
+            .latest(self.channel.as_deref(), version_req.as_ref(), &host)
+            .ok_or_else(|| RemoteError::NoMatchingVersion {
+                url: self.url.clone(),
+                target: host.to_string(),
+            })
+    }
+
+    async fn download_entry(
+        &self,
+        entry: &IndexedVersion,
+        remote: &RemotePackage,
+        global_folder: &GlobalFolders,
+    ) -> Result<DownloadedArtifact, RemoteError> {
+        let url = Url::parse(&self.url)?.join(&entry.url)?;
+        info!("Downloading {} from {}", entry.version, url);
+        let path = new_download_path(remote, global_folder)?;
+
+        let actual = match url_to_file_path(&url)? {
+            Some(source) => {
+                let mut file = HashingWriter::new(File::create(&path)?);
+                std::io::copy(&mut File::open(source)?, &mut file)?;
+                file.hash()
+            }
+            None => write_response_body(self.get(&url).await?, &path).await?,
+        };
+
+        if !actual.eq_ignore_ascii_case(&entry.sha256) {
+            fs::remove_file(&path)?;
+            return Err(RemoteError::ChecksumMismatch {
+                url: url.to_string(),
+                expected: entry.sha256.clone(),
+                actual,
+            });
+        }
+
+        Ok(DownloadedArtifact {
+            path,
+            etag: Some(entry.sha256.clone()),
+            last_modified: None,
+            source: None,
+            trusted_keys: Vec::new(),
+            install_limits: InstallLimits::default(),
+        })
+    }
+
+    /// Index and artifact URLs may be `file://` URLs, otherwise they are fetched over HTTP(S).
+    async fn fetch(&self, url: &Url) -> Result<Vec<u8>, RemoteError> {
+        match url_to_file_path(url)? {
+            Some(path) => Ok(fs::read(path)?),
+            None => Ok(self.get(url).await?.bytes().await?.to_vec()),
+        }
+    }
+
+    async fn get(&self, url: &Url) -> Result<reqwest::Response, RemoteError> {
+        let http = HttpPackageRepository {
+            url: url.to_string(),
+            target_urls: BTreeMap::default(),
+            auth_strategy: self.auth_strategy.clone(),
+        };
+        Ok(http
+            .make_request(Method::GET)?
+            .send()
+            .await?
+            .error_for_status()?)
+    }
+}
+
+fn url_to_file_path(url: &Url) -> Result<Option<PathBuf>, RemoteError> {
+    if url.scheme() != "file" {
+        return Ok(None);
+    }
+
+    match url.to_file_path() {
+        Ok(path) => Ok(Some(path)),
+        Err(_) => Err(anyhow::anyhow!("{} is not a valid file path", url).into()),
+    }
+}
+
+/// Download a specific version from a remote, instead of the version it's following.
+/// Only index remotes list more than one version.
+pub async fn download_remote_version(
     remote: RemotePackage,
+    version: &str,
+    global_folder: &GlobalFolders,
+) -> Result<(String, DownloadedArtifact), RemoteError> {
+    let index_repo = match &remote.repository {
+        PackageRepository::Index(index_repo) => index_repo,
+        _ => {
+            return Err(RemoteError::VersionsNotSupported {
+                name: remote.name.clone(),
+            })
+        }
+    };
+
+    let index = index_repo.fetch_index().await?;
+    let host = PackageTarget::current();
+    let entry =
+        index
+            .find_version(version, &host)
+            .ok_or_else(|| RemoteError::VersionNotInIndex {
+                url: index_repo.url.clone(),
+                version: version.to_string(),
+                target: host.to_string(),
+            })?;
+
+    let mut artifact = index_repo
+        .download_entry(entry, &remote, global_folder)
+        .await?;
+    artifact.source = Some(PackageSource::new(&remote, Some(chrono::Utc::now())));
+    artifact.trusted_keys = remote.trusted_keys.clone();
+    artifact.install_limits = remote.install_limits.clone().unwrap_or_default();
+    Ok((entry.version.clone(), artifact))
+}
+
+/// The remote that installed `name` before, or the remote with the same name as the package.
+pub fn find_remote_for_package(
+    container: &StateContainer,
+    name: &str,
+    global_folder: &GlobalFolders,
+) -> Result<Option<RemotePackage>, RemoteError> {
+    let remote_names: Vec<&String> = container
+        .current_state
+        .installed_versions(name)
+        .into_iter()
+        .filter_map(|x| x.remote_name.as_ref())
+        .collect();
+
+    let remote = load_remote_packages(global_folder)?
+        .into_iter()
+        .find(|remote| remote.name == name || remote_names.contains(&&remote.name));
+
+    Ok(remote)
+}
+
+/// Read every remote config in the remote config dir.
+pub fn load_remote_packages(
+    global_folder: &GlobalFolders,
+) -> Result<Vec<RemotePackage>, RemoteError> {
+    let remote_folder = global_folder.get_remote_config_dir();
+    let mut remote_packages = Vec::new();
+
+    if !remote_folder.exists() {
+        return Ok(remote_packages);
+    }
+
+    for entry in fs::read_dir(remote_folder)? {
+        let entry = entry?;
+        debug!("Processing remote file {:?}", entry.path());
+        let contents = fs::read_to_string(entry.path())?;
+        remote_packages.push(serde_json::from_str(&contents)?);
+    }
+
+    Ok(remote_packages)
+}
+
+/// Start `toolup remote update` in the background for the remote that provides `binary_name`,
+/// which updates it if it's past its update period.
+///
+/// This is called by `toolup-shim` on every invocation, so it only stats the update marker
+/// and never waits on the update. The remote's config is left to the background update.
+pub fn spawn_background_update(
+    container: &StateContainer,
+    binary_name: &str,
+    global_folder: &GlobalFolders,
+) -> Result<(), RemoteError> {
+    if auto_update_disabled(std::env::var(TOOLUP_DISABLE_AUTO_UPDATE).ok().as_deref()) {
+        return Ok(());
+    }
+
+    let state = &container.current_state;
+    let remote_name = match state
+        .current_binaries
+        .get(binary_name)
+        .and_then(|binary| state.installed_packages.get(&binary.package_id))
+        .and_then(|package| package.remote_name.clone())
+    {
+        Some(remote_name) => remote_name,
+        None => return Ok(()),
+    };
+
+    let backed_off = |elapsed: i64| elapsed < BACKGROUND_UPDATE_BACKOFF_SECONDS as i64;
+    if let Some(last_checked) = state.remote_last_checked.get(&remote_name) {
+        if backed_off(
+            chrono::Utc::now()
+                .signed_duration_since(*last_checked)
+                .num_seconds(),
+        ) {
+            return Ok(());
+        }
+    }
+
+    let marker = global_folder.get_update_marker(&remote_name);
+    if let Ok(modified) = fs::metadata(&marker).and_then(|x| x.modified()) {
+        if let Ok(elapsed) = modified.elapsed() {
+            if backed_off(elapsed.as_secs() as i64) {
+                return Ok(());
+            }
+        }
+    }
+
+    fs::create_dir_all(global_folder.get_remote_download_dir())?;
+    fs::write(&marker, chrono::Utc::now().to_rfc3339())?;
+
+    let mut toolup = std::env::current_exe()?;
+    toolup.pop();
+    toolup.push("toolup");
+    // Without --force, the update skips the remote when it isn't past its update period.
+    spawn_detached(&toolup, &["remote", "update", "--only", &remote_name])?;
+
+    Ok(())
+}
+
+/// Only `1` and `true` turn off background updates, so `TOOLUP_DISABLE_AUTO_UPDATE=0` leaves them on.
+fn auto_update_disabled(value: Option<&str>) -> bool {
+    matches!(value, Some("1") | Some("true"))
+}
+
+#[test]
+fn validate_auto_update_disabled() {
+    assert!(auto_update_disabled(Some("1")));
+    assert!(auto_update_disabled(Some("true")));
+    assert!(!auto_update_disabled(Some("0")));
+    assert!(!auto_update_disabled(Some("false")));
+    assert!(!auto_update_disabled(Some("")));
+    assert!(!auto_update_disabled(None));
+}
+
+/// Download the remote's latest archive, without checking it against the remote's pinned sha256.
+pub async fn download_remote(
+    remote: &RemotePackage,
     global_folder: &GlobalFolders,
 ) -> Result<DownloadedArtifact, RemoteError> {
     match &remote.repository {
-        PackageRepository::S3(s3) => s3.download(&remote, global_folder).await,
-        PackageRepository::Local(local) => local.download(&remote, global_folder).await,
+        PackageRepository::S3(s3) => s3.download(remote, global_folder).await,
+        PackageRepository::Local(local) => local.download(remote, global_folder).await,
+        PackageRepository::Http(http) => http.download(remote, global_folder).await,
+        PackageRepository::Git(git) => git.download(remote, global_folder).await,
+        PackageRepository::Index(index) => index.download(remote, global_folder).await,
     }
 }
 
+/// Delete a downloaded archive once it's no longer needed.
+///
+/// Local remotes point at the archive itself, so only files in the download dir are removed.
+pub fn cleanup_download(
+    artifact: &DownloadedArtifact,
+    global_folder: &GlobalFolders,
+) -> Result<(), std::io::Error> {
+    if artifact
+        .path
+        .starts_with(global_folder.get_remote_download_dir())
+    {
+        debug!("Removing file {:?}", artifact);
+        fs::remove_file(&artifact.path)?;
+    }
+
+    Ok(())
+}
+
+pub async fn update_remote(
+    remote: RemotePackage,
+    global_folder: &GlobalFolders,
+) -> Result<DownloadedArtifact, RemoteError> {
+    let mut artifact = download_remote(&remote, global_folder).await?;
+
+    if let Some(expected) = &remote.sha256 {
+        let actual = get_hash_for_file(&artifact.path)?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            cleanup_download(&artifact, global_folder)?;
+            return Err(RemoteError::ChecksumMismatch {
+                url: remote.repository.location().to_string(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+
+    artifact.source = Some(PackageSource::new(&remote, Some(chrono::Utc::now())));
+    artifact.trusted_keys = remote.trusted_keys;
+    artifact.install_limits = remote.install_limits.unwrap_or_default();
+    Ok(artifact)
+}
+
 pub async fn package_needs_update(
     remote: &RemotePackage,
     etag: Option<String>,
+    last_modified: Option<String>,
 ) -> Result<bool, RemoteError> {
     match &remote.repository {
-        PackageRepository::S3(s3) => s3.needs_update(remote, etag).await,
-        PackageRepository::Local(local) => local.needs_update(remote, etag).await,
+        PackageRepository::S3(s3) => s3.needs_update(remote, etag, last_modified).await,
+        PackageRepository::Local(local) => local.needs_update(remote, etag, last_modified).await,
+        PackageRepository::Http(http) => http.needs_update(remote, etag, last_modified).await,
+        PackageRepository::Git(git) => git.needs_update(remote, etag, last_modified).await,
+        PackageRepository::Index(index) => index.needs_update(remote, etag, last_modified).await,
+    }
+}
+
+#[cfg(test)]
+use crate::test_util::*;
+
+#[cfg(test)]
+async fn serve_artifact(body: &'static str, etag: &'static str) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/package.tar.gz", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = socket.read(&mut buffer).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+
+            let request = String::from_utf8_lossy(&request).to_lowercase();
+            let (status, body) = if !request.contains("authorization: bearer secret") {
+                ("401 Unauthorized", "")
+            } else if request.contains(&format!("if-none-match: {}", etag)) {
+                ("304 Not Modified", "")
+            } else if request.starts_with("head") {
+                ("200 OK", "")
+            } else {
+                ("200 OK", body)
+            };
+
+            let response = format!(
+                "HTTP/1.1 {}\r\netag: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                etag,
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+
+    url
+}
+
+#[tokio::test]
+async fn http_remote_downloads_with_etag() {
+    use crate::model::AuthScript;
+
+    let url = serve_artifact("package contents", "\"abc\"").await;
+    let (temp_dir, global_folder) = temp_global_folder();
+
+    let http = HttpPackageRepository {
+        url,
+        target_urls: BTreeMap::default(),
+        auth_strategy: HttpAuthStrategy::Bearer(AuthScript {
+            script_path: format!("{}/test/stub-http-auth.sh", env!("CARGO_MANIFEST_DIR")),
+        }),
+    };
+    let remote = test_remote("foo", PackageRepository::Http(http.clone()));
+
+    assert!(http.needs_update(&remote, None, None).await.unwrap());
+
+    let artifact = http.download(&remote, &global_folder).await.unwrap();
+    assert_eq!(
+        "package contents",
+        fs::read_to_string(&artifact.path).unwrap()
+    );
+    assert_eq!(Some("\"abc\"".to_string()), artifact.etag);
+
+    assert!(!http
+        .needs_update(&remote, artifact.etag, None)
+        .await
+        .unwrap());
+    let old_etag = Some("\"old\"".to_string());
+    assert!(http.needs_update(&remote, old_etag, None).await.unwrap());
+
+    let anonymous = HttpPackageRepository {
+        auth_strategy: HttpAuthStrategy::None,
+        ..http.clone()
+    };
+    assert!(anonymous.download(&remote, &global_folder).await.is_err());
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
+
+#[tokio::test]
+async fn pinned_checksum_rejects_changed_archive() {
+    use crate::model::AuthScript;
+    use sha2::{Digest, Sha256};
+
+    let url = serve_artifact("package contents", "\"abc\"").await;
+    let (temp_dir, global_folder) = temp_global_folder();
+
+    let make_remote = |sha256: &str| RemotePackage {
+        sha256: Some(sha256.to_string()),
+        ..test_remote(
+            "foo",
+            PackageRepository::Http(HttpPackageRepository {
+                url: url.clone(),
+                target_urls: BTreeMap::default(),
+                auth_strategy: HttpAuthStrategy::Bearer(AuthScript {
+                    script_path: format!("{}/test/stub-http-auth.sh", env!("CARGO_MANIFEST_DIR")),
+                }),
+            }),
+        )
+    };
+
+    let expected = format!("{:x}", Sha256::digest(b"package contents"));
+    let artifact = update_remote(make_remote(&expected.to_uppercase()), &global_folder)
+        .await
+        .unwrap();
+    assert_eq!(
+        "package contents",
+        fs::read_to_string(&artifact.path).unwrap()
+    );
+
+    let frozen = format!("{:x}", Sha256::digest(b"old contents"));
+    assert!(matches!(
+        update_remote(make_remote(&frozen), &global_folder).await,
+        Err(RemoteError::ChecksumMismatch { actual, .. }) if actual == expected
+    ));
+
+    // `--print-checksums` downloads without the pin, so a wrong pin can be fixed.
+    let artifact = download_remote(&make_remote(&frozen), &global_folder)
+        .await
+        .unwrap();
+    assert_eq!(expected, get_hash_for_file(&artifact.path).unwrap());
+    cleanup_download(&artifact, &global_folder).unwrap();
+    assert!(!artifact.path.exists());
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
+
+#[tokio::test]
+async fn git_remote_downloads_commit() {
+    let (temp_dir, global_folder) = temp_global_folder();
+
+    let source_dir = temp_dir.join("source");
+    let commit = {
+        let repo = Repository::init(&source_dir).unwrap();
+        fs::write(source_dir.join("package.tar.gz"), "package contents").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("package.tar.gz")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("toolup", "toolup@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap()
+            .to_string()
+    };
+
+    let git = GitPackageRepository {
+        url: source_dir.display().to_string(),
+        reference: None,
+        artifact: GitArtifact::Archive {
+            archive_path: "package.tar.gz".to_string(),
+        },
+    };
+    let remote = test_remote("foo", PackageRepository::Git(git.clone()));
+
+    assert!(git.needs_update(&remote, None, None).await.unwrap());
+
+    let artifact = git.download(&remote, &global_folder).await.unwrap();
+    assert_eq!(
+        "package contents",
+        fs::read_to_string(&artifact.path).unwrap()
+    );
+    assert_eq!(Some(commit), artifact.etag);
+    assert!(!git
+        .needs_update(&remote, artifact.etag, None)
+        .await
+        .unwrap());
+
+    let missing = GitPackageRepository {
+        reference: Some("missing".to_string()),
+        ..git
+    };
+    assert!(missing.download(&remote, &global_folder).await.is_err());
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
+
+#[tokio::test]
+async fn index_remote_follows_channel() {
+    let (temp_dir, global_folder) = temp_global_folder();
+
+    let index_dir = temp_dir.join("index");
+    fs::create_dir_all(&index_dir).unwrap();
+    let mut versions = Vec::new();
+    for (version, channel) in [("1.2.0", "stable"), ("1.10.0", "stable"), ("2.0.0", "beta")] {
+        let contents = format!("foo {}", version);
+        let mut writer = HashingWriter::new(File::create(index_dir.join(version)).unwrap());
+        writer.write_all(contents.as_bytes()).unwrap();
+        versions.push(IndexedVersion {
+            version: version.to_string(),
+            url: version.to_string(),
+            sha256: writer.hash(),
+            channels: vec![channel.to_string()],
+            target: None,
+        });
     }
+    fs::write(
+        index_dir.join("index.json"),
+        serde_json::to_string(&PackageIndex { versions }).unwrap(),
+    )
+    .unwrap();
+
+    let index = IndexPackageRepository {
+        url: Url::from_file_path(index_dir.join("index.json"))
+            .unwrap()
+            .to_string(),
+        channel: Some("stable".to_string()),
+        version_req: None,
+        auth_strategy: HttpAuthStrategy::None,
+    };
+    let remote = test_remote("foo", PackageRepository::Index(index.clone()));
+
+    let artifact = index.download(&remote, &global_folder).await.unwrap();
+    assert_eq!("foo 1.10.0", fs::read_to_string(&artifact.path).unwrap());
+    assert!(!index
+        .needs_update(&remote, artifact.etag, None)
+        .await
+        .unwrap());
+
+    // A partial version only follows its patches, like everywhere else
+    let pinned = IndexPackageRepository {
+        version_req: Some("1.2".to_string()),
+        ..index.clone()
+    };
+    let artifact = pinned.download(&remote, &global_folder).await.unwrap();
+    assert_eq!("foo 1.2.0", fs::read_to_string(&artifact.path).unwrap());
+
+    let beta = IndexPackageRepository {
+        channel: Some("beta".to_string()),
+        version_req: Some("^1".to_string()),
+        ..index
+    };
+    assert!(beta.needs_update(&remote, None, None).await.is_err());
+
+    fs::write(index_dir.join("2.0.0"), "tampered").unwrap();
+    let beta = IndexPackageRepository {
+        version_req: None,
+        ..beta
+    };
+    assert!(matches!(
+        beta.download(&remote, &global_folder).await,
+        Err(RemoteError::ChecksumMismatch { .. })
+    ));
+
+    let (version, artifact) = download_remote_version(remote, "1.2", &global_folder)
+        .await
+        .unwrap();
+    assert_eq!("1.2.0", version);
+    assert_eq!("foo 1.2.0", fs::read_to_string(&artifact.path).unwrap());
+
+    fs::remove_dir_all(temp_dir).unwrap();
 }
@@ -0,0 +1,243 @@
+use crate::package::{read_package_definition, PackageError};
+use crate::state::{InstalledBinary, InstalledState, StateError};
+use crate::util::PROJECT_FILE_NAME;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tracing::debug;
+
+#[derive(Error, Debug)]
+pub enum ResolveError {
+    #[error("Unable to parse {path}: {error}")]
+    InvalidProjectFile {
+        path: String,
+        error: toml::de::Error,
+    },
+    #[error("{path} pins {name}@{version}, but no matching version that provides {binary} is installed.")]
+    PinnedVersionNotInstalled {
+        path: String,
+        name: String,
+        version: String,
+        binary: String,
+    },
+    #[error(transparent)]
+    State(#[from] StateError),
+    #[error(transparent)]
+    Package(#[from] PackageError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// A `.toolup.toml`, which pins the versions of packages used inside of a project.
+///
+/// ```toml
+/// [packages]
+/// foo = "1.2.3"
+/// bar = "^2"
+/// ```
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProjectConfig {
+    #[serde(default)]
+    pub packages: BTreeMap<String, String>,
+}
+
+impl ProjectConfig {
+    pub fn read(path: &Path) -> Result<Self, ResolveError> {
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|error| ResolveError::InvalidProjectFile {
+            path: path.display().to_string(),
+            error,
+        })
+    }
+}
+
+/// Why a binary was chosen.
+#[derive(Debug, Clone)]
+pub enum ResolveReason {
+    /// The package's current version.
+    Current,
+    /// A version from `--version` or `TOOLUP_VERSION_OVERRIDE`.
+    Override { version: String },
+    /// A version pinned by a `.toolup.toml`.
+    ProjectPin { path: PathBuf, version: String },
+}
+
+impl fmt::Display for ResolveReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveReason::Current => write!(f, "current version"),
+            ResolveReason::Override { version } => write!(f, "version override {}", version),
+            ResolveReason::ProjectPin { path, version } => {
+                write!(f, "pinned to {} by {}", version, path.display())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ResolvedBinary<'a> {
+    pub binary: &'a InstalledBinary,
+    pub reason: ResolveReason,
+}
+
+impl<'a> ResolvedBinary<'a> {
+    /// The environment and leading arguments from the package's `archive.json`, to run the binary with.
+    pub fn exec_options(
+        &self,
+        state: &InstalledState,
+    ) -> Result<(BTreeMap<String, String>, Vec<String>), ResolveError> {
+        let package = match state.installed_packages.get(&self.binary.package_id) {
+            Some(package) => package,
+            None => return Ok(Default::default()),
+        };
+
+        let definition = read_package_definition(Path::new(&package.package_dir))?;
+        Ok(definition.exec_options(&self.binary.name, &package.package_dir))
+    }
+}
+
+/// Find the binary to run for `name`. A version override wins, then the nearest `.toolup.toml`,
+/// walking up from `cwd`, that pins the package. Otherwise the current version is used.
+pub fn resolve_binary<'a>(
+    state: &'a InstalledState,
+    name: &str,
+    version_override: Option<&str>,
+    cwd: &Path,
+) -> Result<ResolvedBinary<'a>, ResolveError> {
+    if let Some(version) = version_override {
+        return Ok(ResolvedBinary {
+            binary: state.find_binary(name, version)?,
+            reason: ResolveReason::Override {
+                version: version.to_string(),
+            },
+        });
+    }
+
+    let package_names: Vec<&String> = state
+        .packages_providing(name)
+        .into_iter()
+        .map(|x| &x.name)
+        .collect();
+
+    if !package_names.is_empty() {
+        for dir in cwd.ancestors() {
+            let path = dir.join(PROJECT_FILE_NAME);
+            if !path.is_file() {
+                continue;
+            }
+
+            let project = ProjectConfig::read(&path)?;
+            debug!("Found project file {} {:?}", path.display(), project);
+            let pin = package_names
+                .iter()
+                .find_map(|package| project.packages.get(*package).map(|x| (*package, x)));
+
+            if let Some((package_name, version)) = pin {
+                let binary =
+                    state
+                        .resolve_version(package_name, version)
+                        .ok()
+                        .and_then(|package| {
+                            state
+                                .installed_binaries
+                                .values()
+                                .find(|x| x.package_id == package.id && x.name == name)
+                        });
+
+                return match binary {
+                    Some(binary) => Ok(ResolvedBinary {
+                        binary,
+                        reason: ResolveReason::ProjectPin {
+                            path,
+                            version: version.clone(),
+                        },
+                    }),
+                    None => Err(ResolveError::PinnedVersionNotInstalled {
+                        path: path.display().to_string(),
+                        name: package_name.clone(),
+                        version: version.clone(),
+                        binary: name.to_string(),
+                    }),
+                };
+            }
+        }
+    }
+
+    Ok(ResolvedBinary {
+        binary: state.find_current_binary(name)?,
+        reason: ResolveReason::Current,
+    })
+}
+
+#[test]
+fn project_pins_win_over_current() {
+    use crate::model::{GeneratedDefinedPackage, InstalledPackageContainer};
+
+    let mut state = InstalledState::default();
+    for version in ["1.2.0", "1.3.0", "2.0.0"] {
+        let container = InstalledPackageContainer {
+            package: GeneratedDefinedPackage {
+                name: "foo".to_string(),
+                entrypoints: BTreeMap::from([("foo".to_string(), "bin/foo".to_string())]),
+                version: version.to_string(),
+                achived_at: chrono::Utc::now(),
+                file_hashes: Default::default(),
+                env: Default::default(),
+                entrypoint_options: Default::default(),
+                aliases: Default::default(),
+                target: None,
+                dependencies: Default::default(),
+            },
+            path_to_root: format!("/tmp/fake/{}", version),
+            source: None,
+            etag: None,
+            last_modified: None,
+        };
+        state.add_installed_package(&container);
+        state.make_package_current(&container, false).unwrap();
+    }
+
+    let temp_dir = std::env::temp_dir().join(format!("toolup-{}", uuid::Uuid::new_v4()));
+    let project_dir = temp_dir.join("project");
+    let nested_dir = project_dir.join("nested");
+    fs::create_dir_all(&nested_dir).unwrap();
+
+    let resolved = resolve_binary(&state, "foo", None, &nested_dir).unwrap();
+    assert_eq!("2.0.0", resolved.binary.version);
+    assert!(matches!(resolved.reason, ResolveReason::Current));
+
+    fs::write(
+        project_dir.join(PROJECT_FILE_NAME),
+        "[packages]\nfoo = \"^1\"\n",
+    )
+    .unwrap();
+    fs::write(
+        nested_dir.join(PROJECT_FILE_NAME),
+        "[packages]\nbar = \"1\"\n",
+    )
+    .unwrap();
+    let resolved = resolve_binary(&state, "foo", None, &nested_dir).unwrap();
+    assert_eq!("1.3.0", resolved.binary.version);
+    assert!(
+        matches!(resolved.reason, ResolveReason::ProjectPin { path, .. } if path == project_dir.join(PROJECT_FILE_NAME))
+    );
+
+    let resolved = resolve_binary(&state, "foo", Some("1.2"), &nested_dir).unwrap();
+    assert_eq!("1.2.0", resolved.binary.version);
+
+    fs::write(
+        nested_dir.join(PROJECT_FILE_NAME),
+        "[packages]\nfoo = \"^3\"\n",
+    )
+    .unwrap();
+    assert!(matches!(
+        resolve_binary(&state, "foo", None, &nested_dir),
+        Err(ResolveError::PinnedVersionNotInstalled { .. })
+    ));
+
+    fs::remove_dir_all(temp_dir).unwrap();
+}
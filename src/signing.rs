@@ -0,0 +1,98 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use std::convert::TryInto;
+use std::path::Path;
+use thiserror::Error;
+
+use crate::model::PackageSignature;
+
+#[derive(Error, Debug)]
+pub enum SigningError {
+    #[error("`{path}` doesn't contain a base64 encoded ed25519 secret key.")]
+    InvalidSecretKey { path: String },
+    #[error("`{key}` isn't a base64 encoded ed25519 public key.")]
+    InvalidPublicKey { key: String },
+    #[error("The signature doesn't match what was signed.")]
+    BadSignature,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Generate a new key pair. Returns the secret key and the public key, both base64 encoded.
+pub fn generate_key() -> (String, String) {
+    let key = SigningKey::generate(&mut rand_core::OsRng);
+    (
+        base64::encode(key.to_bytes()),
+        base64::encode(key.verifying_key().to_bytes()),
+    )
+}
+
+/// Read a secret key written by `generate_key`.
+pub fn read_signing_key(path: &Path) -> Result<SigningKey, SigningError> {
+    let contents = std::fs::read_to_string(path)?;
+    let bytes: [u8; 32] = base64::decode(contents.trim())
+        .ok()
+        .and_then(|x| x.try_into().ok())
+        .ok_or_else(|| SigningError::InvalidSecretKey {
+            path: path.display().to_string(),
+        })?;
+
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+pub fn parse_public_key(key: &str) -> Result<VerifyingKey, SigningError> {
+    let invalid = || SigningError::InvalidPublicKey {
+        key: key.to_string(),
+    };
+    let bytes: [u8; 32] = base64::decode(key.trim())
+        .ok()
+        .and_then(|x| x.try_into().ok())
+        .ok_or_else(invalid)?;
+
+    VerifyingKey::from_bytes(&bytes).map_err(|_| invalid())
+}
+
+pub fn sign(key: &SigningKey, contents: &[u8]) -> PackageSignature {
+    PackageSignature {
+        public_key: base64::encode(key.verifying_key().to_bytes()),
+        signature: base64::encode(key.sign(contents).to_bytes()),
+    }
+}
+
+/// Check that `signature` was made over `contents` by the key it names.
+///
+/// This says nothing about whether that key is trusted, callers need to check that themselves.
+pub fn verify(contents: &[u8], signature: &PackageSignature) -> Result<(), SigningError> {
+    let key = parse_public_key(&signature.public_key)?;
+    let bytes: [u8; 64] = base64::decode(&signature.signature)
+        .ok()
+        .and_then(|x| x.try_into().ok())
+        .ok_or(SigningError::BadSignature)?;
+
+    key.verify_strict(contents, &Signature::from_bytes(&bytes))
+        .map_err(|_| SigningError::BadSignature)
+}
+
+#[test]
+fn validate_sign_and_verify() {
+    let temp_dir = std::env::temp_dir().join(format!("toolup-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&temp_dir).unwrap();
+    let (secret, public) = generate_key();
+    std::fs::write(temp_dir.join("key"), secret).unwrap();
+
+    let key = read_signing_key(&temp_dir.join("key")).unwrap();
+    let signature = sign(&key, b"contents");
+    assert_eq!(signature.public_key, public);
+    assert!(verify(b"contents", &signature).is_ok());
+    assert!(verify(b"changed", &signature).is_err());
+
+    let (_, other_public) = generate_key();
+    let forged = PackageSignature {
+        public_key: other_public,
+        signature: signature.signature,
+    };
+    assert!(verify(b"contents", &forged).is_err());
+
+    assert!(parse_public_key("not a key").is_err());
+
+    std::fs::remove_dir_all(temp_dir).unwrap();
+}
@@ -1,4 +1,5 @@
-use crate::util::{create_link, GlobalFolders};
+use crate::model::{PackageSource, RemotePackage};
+use crate::util::{compare_versions, create_link, GlobalFolders};
 use chrono::{DateTime, Utc};
 use fs2::FileExt;
 use serde::{Deserialize, Serialize};
@@ -11,6 +12,8 @@ use thiserror::Error;
 use tracing::field::debug as tracing_wrap;
 use tracing::{debug, error};
 
+pub use v1::{InstalledBinary, InstalledPackage, InstalledState};
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct GlobalInstalledState {
@@ -54,12 +57,20 @@ pub struct StateContainer {
 }
 
 impl StateContainer {
+    /// Every installed package, sorted by name and then highest version first.
     pub fn list_installed_packages(&self) -> Vec<PackageDescription> {
-        self.current_state
+        let mut packages: Vec<PackageDescription> = self
+            .current_state
             .installed_packages
             .values()
             .map(|x| self.current_state.describe_package(x))
-            .collect()
+            .collect();
+        packages.sort_by(|a, b| {
+            a.name
+                .cmp(&b.name)
+                .then_with(|| compare_versions(&b.version, &a.version))
+        });
+        packages
     }
 
     pub fn describe_package(&self, name: &str) -> Option<PackageDescription> {
@@ -69,6 +80,53 @@ impl StateContainer {
             .map(|x| self.current_state.describe_package(x))
     }
 
+    /// Describe the current package that was installed by the remote.
+    pub fn describe_remote_package(&self, remote_name: &str) -> Option<PackageDescription> {
+        self.current_state
+            .current_packages
+            .values()
+            .find(|x| x.remote_name.as_deref() == Some(remote_name))
+            .map(|x| self.current_state.describe_package(x))
+    }
+
+    /// Packages installed before sources were recorded don't know which remote they came from.
+    /// Attach the remote with the same name as the package, returning true if anything changed.
+    pub fn migrate_package_sources(&mut self, remotes: &[RemotePackage]) -> bool {
+        let state = &mut self.current_state;
+        let mut changed = false;
+        for package in state
+            .installed_packages
+            .values_mut()
+            .chain(state.current_packages.values_mut())
+        {
+            if package.remote_name.is_some() || package.source.is_some() {
+                continue;
+            }
+
+            if let Some(remote) = remotes.iter().find(|x| x.name == package.name) {
+                debug!("Attaching remote {} to {:?}", remote.name, package);
+                package.remote_name = Some(remote.name.clone());
+                package.source = Some(PackageSource::new(remote, None));
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    /// True when the remote has not been checked for updates within its update period.
+    pub fn is_remote_update_due(&self, remote: &RemotePackage) -> bool {
+        match self.current_state.remote_last_checked.get(&remote.name) {
+            None => true,
+            Some(last_checked) => {
+                Utc::now()
+                    .signed_duration_since(*last_checked)
+                    .num_seconds()
+                    >= remote.update_period_seconds
+            }
+        }
+    }
+
     pub fn remove_packages(&mut self, packages_to_remove: Vec<PackageDescription>) {
         for package in packages_to_remove {
             self.current_state.remove_package_by_id(&package.package_id);
@@ -187,7 +245,11 @@ async fn with_write_lock(
 
     debug!("Will write {:?} to {:?}", new_state, state_path);
     let state_contents = serde_json::to_string_pretty(&new_state)?;
-    fs::write(state_path, state_contents)?;
+
+    // Write to a temp file and rename it, so a failed write never leaves a partial state file.
+    let temp_path = state_path.with_extension("json.tmp");
+    fs::write(&temp_path, state_contents)?;
+    fs::rename(&temp_path, state_path)?;
 
     debug!("State file {:?} written successfully", state_path);
 
@@ -236,7 +298,7 @@ pub async fn update_links(
 
     for missing_binary in installed_tools {
         let binary_link = Path::join(&link_dir, missing_binary);
-        if binary_link.exists() {
+        if binary_link.exists() || binary_link.is_symlink() {
             debug!("Removing {:?}", binary_link);
             std::fs::remove_file(&binary_link)?;
         }
@@ -247,25 +309,33 @@ pub async fn update_links(
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct PackageDescription {
     pub name: String,
     pub version: String,
     pub binaries: BTreeMap<String, bool>,
     pub remote_name: Option<String>,
+    pub source: Option<PackageSource>,
     pub etag: Option<String>,
+    pub last_modified: Option<String>,
     pub package_id: String,
+    pub package_dir: String,
 }
 
 mod v1 {
     use super::StateError;
-    use crate::model::{GenericPackage, InstalledPackageContainer};
+    use crate::model::{
+        GeneratedDefinedPackage, GenericPackage, InstalledPackageContainer, PackageSource,
+    };
+    use crate::util::{compare_versions, version_matches};
+    use chrono::{DateTime, Utc};
     use derivative::Derivative;
     use serde::{Deserialize, Serialize};
     use std::collections::BTreeMap;
     use std::hash::{Hash, Hasher};
     use std::path::Path;
-    use tracing::{debug, warn};
+    use tracing::{debug, info, warn};
 
     #[derive(Serialize, Deserialize, Derivative, Debug, Clone)]
     #[serde(rename_all = "kebab-case")]
@@ -277,7 +347,12 @@ mod v1 {
         #[derivative(PartialEq = "ignore")]
         pub package_dir: String,
         pub remote_name: Option<String>,
+        #[serde(default)]
+        #[derivative(PartialEq = "ignore")]
+        pub source: Option<PackageSource>,
         pub etag: Option<String>,
+        #[serde(default)]
+        pub last_modified: Option<String>,
     }
 
     impl GenericPackage for &InstalledPackage {
@@ -311,8 +386,10 @@ mod v1 {
                 name: container.package.name.clone(),
                 version: container.package.version.clone(),
                 package_dir: container.path_to_root.clone(),
-                remote_name: container.remote_name.clone(),
+                remote_name: container.source.as_ref().map(|x| x.remote_name.clone()),
+                source: container.source.clone(),
                 etag: container.etag.clone(),
+                last_modified: container.last_modified.clone(),
             }
         }
     }
@@ -365,6 +442,12 @@ mod v1 {
         pub current_binaries: BTreeMap<String, InstalledBinary>,
         #[serde(default = "Default::default")]
         pub current_packages: BTreeMap<String, InstalledPackage>,
+        /// When each remote was last checked for an update, keyed by remote name.
+        #[serde(default = "Default::default")]
+        pub remote_last_checked: BTreeMap<String, DateTime<Utc>>,
+        /// The package that owns each link in the bin dir, keyed by binary name.
+        #[serde(default = "Default::default")]
+        pub link_owners: BTreeMap<String, String>,
     }
 
     impl InstalledState {
@@ -389,8 +472,11 @@ mod v1 {
                 version: package.version.clone(),
                 binaries: binaries_installed,
                 remote_name: package.remote_name.clone(),
+                source: package.source.clone(),
                 package_id: package.id.clone(),
                 etag: package.etag.clone(),
+                last_modified: package.last_modified.clone(),
+                package_dir: package.package_dir.clone(),
             }
         }
 
@@ -417,7 +503,16 @@ mod v1 {
                 self.current_binaries.remove(&binary_name);
             }
 
-            self.installed_packages.remove(id);
+            self.current_packages.retain(|_, package| package.id != id);
+            if let Some(package) = self.installed_packages.remove(id) {
+                if !self
+                    .installed_packages
+                    .values()
+                    .any(|x| x.name == package.name)
+                {
+                    self.link_owners.retain(|_, owner| *owner != package.name);
+                }
+            }
         }
 
         pub fn remove_packages(&mut self, packages_to_remove: Vec<InstalledPackage>) {
@@ -445,9 +540,40 @@ mod v1 {
             }
         }
 
+        /// The package that owns the link for `binary_name`. State written before owners were
+        /// recorded falls back to the package of the current binary.
+        pub fn link_owner(&self, binary_name: &str) -> Option<String> {
+            if let Some(owner) = self.link_owners.get(binary_name) {
+                return Some(owner.clone());
+            }
+
+            self.current_binaries
+                .get(binary_name)
+                .and_then(|x| self.installed_packages.get(&x.package_id))
+                .map(|x| x.name.clone())
+        }
+
+        /// Entrypoints of `package` whose links are owned by a different package, mapped to that owner.
+        pub fn find_conflicts(
+            &self,
+            package: &GeneratedDefinedPackage,
+        ) -> BTreeMap<String, String> {
+            package
+                .entrypoints
+                .keys()
+                .filter_map(|name| match self.link_owner(name) {
+                    Some(owner) if owner != package.name => Some((name.clone(), owner)),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        /// Make the package the current version. Links owned by other packages are left alone,
+        /// unless `take_over` is set.
         pub fn make_package_current(
             &mut self,
             container: &InstalledPackageContainer,
+            take_over: bool,
         ) -> Result<(), StateError> {
             let package = InstalledPackage::from(container);
 
@@ -477,15 +603,25 @@ mod v1 {
 
             for binary in self.installed_binaries.values() {
                 if binary.package_id == package.id {
+                    match self.link_owner(&binary.name) {
+                        Some(owner) if owner != package.name && !take_over => {
+                            warn!(target: "user", "Not linking {}, it belongs to {}. Use --take-over to replace it.", binary.name, owner);
+                            continue;
+                        }
+                        Some(owner) if owner != package.name => {
+                            warn!(target: "user", "{} is taking over {} from {}", package.name, binary.name, owner);
+                        }
+                        _ => {}
+                    }
+
                     let existing = self
                         .current_binaries
                         .insert(binary.name.clone(), binary.clone());
                     if let Some(old_binary) = existing {
-                        if old_binary.package_id != package.id {
-                            warn!(target: "user", "{} is replacing a managed binary", package.name);
-                        }
                         debug!("Removing old binary {:?}.", old_binary);
                     }
+                    self.link_owners
+                        .insert(binary.name.clone(), package.name.clone());
 
                     debug!("Setting binary {:?} to current.", binary);
                 }
@@ -498,33 +634,146 @@ mod v1 {
                 debug!("Replacing existing packag{:?} with {:?}", existing, package);
             }
 
+            self.relink_orphaned_binaries();
             Ok(())
         }
 
-        pub fn get_current_binary_path(&self, name: &str) -> Result<String, StateError> {
-            let binary = match self.current_binaries.get(name) {
-                None => {
-                    return Err(StateError::NoSuchBinary {
-                        name: name.to_string(),
-                        version: "CURRENT".to_string(),
-                    })
+        /// Link each binary that nothing links anymore, because the package that owned it was
+        /// removed or stopped providing it, to another current package that provides it.
+        pub fn relink_orphaned_binaries(&mut self) {
+            let mut orphans: BTreeMap<String, (InstalledBinary, String)> = BTreeMap::new();
+            for package in self.current_packages.values() {
+                for binary in self.installed_binaries.values() {
+                    if binary.package_id == package.id
+                        && !self.current_binaries.contains_key(&binary.name)
+                    {
+                        orphans
+                            .entry(binary.name.clone())
+                            .or_insert_with(|| (binary.clone(), package.name.clone()));
+                    }
                 }
-                Some(binary) => binary,
-            };
+            }
+
+            for (name, (binary, owner)) in orphans {
+                info!(target: "user", "Linking {} to {}, which also provides it", name, owner);
+                self.current_binaries.insert(name.clone(), binary);
+                self.link_owners.insert(name, owner);
+            }
+        }
+
+        pub fn find_package(
+            &self,
+            name: &str,
+            version: &str,
+        ) -> Result<&InstalledPackage, StateError> {
+            let id = crate::util::make_package_id(name, version);
+            match self.installed_packages.get(&id) {
+                Some(package) => Ok(package),
+                None => Err(StateError::PackageNotInstalled {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                }),
+            }
+        }
 
-            Ok(binary.path_to_exec.clone())
+        /// All the installed versions of a package, highest version first.
+        pub fn installed_versions(&self, name: &str) -> Vec<&InstalledPackage> {
+            let mut packages: Vec<&InstalledPackage> = self
+                .installed_packages
+                .values()
+                .filter(|x| x.name == name)
+                .collect();
+            packages.sort_by(|a, b| compare_versions(&b.version, &a.version));
+            packages
+        }
+
+        /// The installed package named exactly `version`, otherwise the highest installed version
+        /// matching it as a semver range.
+        pub fn resolve_version(
+            &self,
+            name: &str,
+            version: &str,
+        ) -> Result<&InstalledPackage, StateError> {
+            if let Ok(package) = self.find_package(name, version) {
+                return Ok(package);
+            }
+
+            match self
+                .installed_versions(name)
+                .into_iter()
+                .find(|x| version_matches(&x.version, version))
+            {
+                Some(package) => Ok(package),
+                None => Err(StateError::PackageNotInstalled {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                }),
+            }
+        }
+
+        pub fn find_current_package(&self, name: &str) -> Result<&InstalledPackage, StateError> {
+            match self.current_packages.get(name) {
+                Some(package) => Ok(package),
+                None => Err(StateError::PackageNotInstalled {
+                    name: name.to_string(),
+                    version: "CURRENT".to_string(),
+                }),
+            }
+        }
+
+        pub fn get_current_binary_path(&self, name: &str) -> Result<String, StateError> {
+            Ok(self.find_current_binary(name)?.path_to_exec.clone())
+        }
+
+        pub fn find_current_binary(&self, name: &str) -> Result<&InstalledBinary, StateError> {
+            match self.current_binaries.get(name) {
+                Some(binary) => Ok(binary),
+                None => Err(StateError::NoSuchBinary {
+                    name: name.to_string(),
+                    version: "CURRENT".to_string(),
+                }),
+            }
         }
 
         pub fn get_binary_path(&self, name: &str, version: &str) -> Result<String, StateError> {
-            for binary in self.installed_binaries.values() {
-                if binary.name == name && binary.version == version {
-                    return Ok(binary.path_to_exec.clone());
-                }
+            Ok(self.find_binary(name, version)?.path_to_exec.clone())
+        }
+
+        /// Find the binary with exactly `version`, otherwise the highest version matching it as a
+        /// semver range.
+        pub fn find_binary(
+            &self,
+            name: &str,
+            version: &str,
+        ) -> Result<&InstalledBinary, StateError> {
+            let binaries = self.installed_binaries.values().filter(|x| x.name == name);
+            let mut matches: Vec<&InstalledBinary> =
+                binaries.clone().filter(|x| x.version == version).collect();
+            if matches.is_empty() {
+                matches = binaries
+                    .filter(|x| version_matches(&x.version, version))
+                    .collect();
+            }
+
+            match matches
+                .into_iter()
+                .max_by(|a, b| compare_versions(&a.version, &b.version))
+            {
+                Some(binary) => Ok(binary),
+                None => Err(StateError::NoSuchBinary {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                }),
             }
-            Err(StateError::NoSuchBinary {
-                name: name.to_string(),
-                version: version.to_string(),
-            })
+        }
+
+        /// Every installed package that provides a binary named `name`.
+        pub fn packages_providing(&self, name: &str) -> Vec<&InstalledPackage> {
+            self.installed_binaries
+                .values()
+                .filter(|x| x.name == name)
+                .filter_map(|x| self.installed_packages.get(&x.package_id))
+                .collect()
         }
     }
 
@@ -538,7 +787,9 @@ mod v1 {
         assert_eq!(installed_state.installed_binaries.len(), 1);
         assert_eq!(installed_state.installed_packages.len(), 1);
 
-        installed_state.make_package_current(&container).unwrap();
+        installed_state
+            .make_package_current(&container, false)
+            .unwrap();
         assert_eq!(installed_state.current_binaries.len(), 1);
 
         let bin = installed_state.current_binaries.get("bin-1").unwrap();
@@ -552,13 +803,17 @@ mod v1 {
         let mut installed_state = InstalledState::default();
         let container = make_stub_package_container("foo", "1.2.3", 1);
         installed_state.add_installed_package(&container);
-        installed_state.make_package_current(&container).unwrap();
+        installed_state
+            .make_package_current(&container, false)
+            .unwrap();
 
         // Install the package again, at a different path.
         let mut container = make_stub_package_container("foo", "1.2.3", 1);
         container.path_to_root = "/tmp/foo/fake".to_owned();
         installed_state.add_installed_package(&container);
-        installed_state.make_package_current(&container).unwrap();
+        installed_state
+            .make_package_current(&container, false)
+            .unwrap();
 
         assert_eq!(installed_state.current_binaries.len(), 1);
         assert_eq!(installed_state.installed_binaries.len(), 1);
@@ -585,7 +840,9 @@ mod v1 {
 
         // install version
         {
-            installed_state.make_package_current(&container1).unwrap();
+            installed_state
+                .make_package_current(&container1, false)
+                .unwrap();
             let installed_package = InstalledPackage::from(&container1);
             assert_eq!(installed_state.current_binaries.len(), 1);
 
@@ -598,7 +855,9 @@ mod v1 {
 
         // install new version
         {
-            installed_state.make_package_current(&container2).unwrap();
+            installed_state
+                .make_package_current(&container2, false)
+                .unwrap();
             let installed_package = InstalledPackage::from(&container2);
             assert_eq!(installed_state.current_binaries.len(), 3);
 
@@ -623,7 +882,9 @@ mod v1 {
 
         // Roll back to old version, should remove all binaries of package
         {
-            installed_state.make_package_current(&container1).unwrap();
+            installed_state
+                .make_package_current(&container1, false)
+                .unwrap();
             assert_eq!(installed_state.current_binaries.len(), 1);
 
             let installed_package = InstalledPackage::from(&container1);
@@ -635,6 +896,39 @@ mod v1 {
         }
     }
 
+    #[test]
+    fn resolves_highest_matching_version() {
+        let mut installed_state = InstalledState::default();
+        for version in ["1.2.0", "1.10.0", "2.0.0", "nightly"] {
+            installed_state.add_installed_package(&make_stub_package_container("foo", version, 1));
+        }
+
+        let versions: Vec<&str> = installed_state
+            .installed_versions("foo")
+            .into_iter()
+            .map(|x| x.version.as_str())
+            .collect();
+        assert_eq!(vec!["2.0.0", "1.10.0", "1.2.0", "nightly"], versions);
+
+        let resolve = |spec| {
+            installed_state
+                .resolve_version("foo", spec)
+                .unwrap()
+                .version
+                .clone()
+        };
+        assert_eq!("1.10.0", resolve("^1.2"));
+        assert_eq!("2.0.0", resolve("~2"));
+        assert_eq!("1.2.0", resolve("1.2"));
+        assert_eq!("nightly", resolve("nightly"));
+        assert!(installed_state.resolve_version("foo", "^3").is_err());
+
+        assert!(installed_state
+            .get_binary_path("bin-1", ">=1.3, <2")
+            .is_ok());
+        assert!(installed_state.get_binary_path("bin-1", "^3").is_err());
+    }
+
     #[cfg(test)]
     fn fake_binary(
         name: &str,
@@ -660,7 +954,7 @@ mod v1 {
         assert_eq!(installed_state.installed_packages.len(), 0);
 
         let error = installed_state
-            .make_package_current(&container)
+            .make_package_current(&container, false)
             .unwrap_err();
         assert_eq!(error.to_string(), "Package foo@1.2.3 was not installed.");
     }
@@ -675,7 +969,9 @@ mod v1 {
         assert_eq!(installed_state.installed_binaries.len(), 1);
         assert_eq!(installed_state.installed_packages.len(), 1);
 
-        installed_state.make_package_current(&container).unwrap();
+        installed_state
+            .make_package_current(&container, false)
+            .unwrap();
         assert_eq!(installed_state.current_binaries.len(), 1);
 
         let installed_package = installed_state
@@ -689,6 +985,132 @@ mod v1 {
         assert_eq!(installed_state.current_binaries.len(), 0);
         assert_eq!(installed_state.installed_binaries.len(), 0);
         assert_eq!(installed_state.installed_packages.len(), 0);
+        assert_eq!(installed_state.current_packages.len(), 0);
+    }
+
+    #[test]
+    fn conflicting_binaries_need_take_over() {
+        let mut installed_state = InstalledState::default();
+        let foo = make_stub_package_container("foo", "1.2.3", 2);
+        let bar = make_stub_package_container("bar", "1.0.0", 1);
+        installed_state.add_installed_package(&foo);
+        installed_state.add_installed_package(&bar);
+        installed_state.make_package_current(&foo, false).unwrap();
+
+        let conflicts = installed_state.find_conflicts(&bar.package);
+        assert_eq!(
+            conflicts,
+            BTreeMap::from([("bin-1".to_string(), "foo".to_string())])
+        );
+
+        // Without take over, the links stay with foo
+        installed_state.make_package_current(&bar, false).unwrap();
+        let bin = installed_state.current_binaries.get("bin-1").unwrap();
+        assert_eq!(bin.version, "1.2.3");
+        assert_eq!(installed_state.link_owner("bin-1").unwrap(), "foo");
+
+        installed_state.make_package_current(&bar, true).unwrap();
+        let bin = installed_state.current_binaries.get("bin-1").unwrap();
+        assert_eq!(bin.version, "1.0.0");
+        assert_eq!(installed_state.link_owner("bin-1").unwrap(), "bar");
+        assert_eq!(installed_state.link_owner("bin-2").unwrap(), "foo");
+
+        // Switching foo's version doesn't take the link back
+        installed_state.make_package_current(&foo, false).unwrap();
+        assert_eq!(installed_state.link_owner("bin-1").unwrap(), "bar");
+
+        let bar_id = installed_state
+            .current_packages
+            .get("bar")
+            .unwrap()
+            .id
+            .clone();
+        installed_state.remove_package_by_id(&bar_id);
+        assert_eq!(installed_state.link_owner("bin-1"), None);
+
+        // foo still provides it, so it gets the link back
+        installed_state.relink_orphaned_binaries();
+        let bin = installed_state.current_binaries.get("bin-1").unwrap();
+        assert_eq!(bin.version, "1.2.3");
+        assert_eq!(installed_state.link_owner("bin-1").unwrap(), "foo");
+    }
+
+    #[test]
+    fn links_move_to_the_remaining_provider() {
+        let mut installed_state = InstalledState::default();
+        let foo = make_stub_package_container("foo", "1.2.3", 2);
+        let new_foo = make_stub_package_container("foo", "2.0.0", 1);
+        let bar = make_stub_package_container("bar", "1.0.0", 2);
+        installed_state.add_installed_package(&foo);
+        installed_state.add_installed_package(&new_foo);
+        installed_state.add_installed_package(&bar);
+        installed_state.make_package_current(&foo, false).unwrap();
+        installed_state.make_package_current(&bar, false).unwrap();
+        assert_eq!(installed_state.link_owner("bin-2").unwrap(), "foo");
+
+        // foo 2.0.0 doesn't provide bin-2, so bar gets it
+        installed_state
+            .make_package_current(&new_foo, false)
+            .unwrap();
+        let bin = installed_state.current_binaries.get("bin-2").unwrap();
+        assert_eq!(bin.package_id, bar.package.id());
+        assert_eq!(installed_state.link_owner("bin-2").unwrap(), "bar");
+        assert_eq!(installed_state.link_owner("bin-1").unwrap(), "foo");
+    }
+
+    #[test]
+    fn migrate_attaches_matching_remote() {
+        use crate::model::{LocalPackageRepository, PackageRepository};
+
+        let mut container = super::StateContainer::default();
+        let package = make_stub_package_container("foo", "1.2.3", 1);
+        container.current_state.add_installed_package(&package);
+        container
+            .current_state
+            .make_package_current(&package, false)
+            .unwrap();
+
+        let remotes = vec![crate::test_util::test_remote(
+            "foo",
+            PackageRepository::Local(LocalPackageRepository {
+                path: "/tmp/foo.tar.gz".to_string(),
+            }),
+        )];
+
+        assert!(container.migrate_package_sources(&remotes));
+        assert!(!container.migrate_package_sources(&remotes));
+
+        let description = container.describe_remote_package("foo").unwrap();
+        assert_eq!(description.remote_name, Some("foo".to_string()));
+        let source = description.source.unwrap();
+        assert_eq!(source.repository_kind, "local");
+        assert_eq!(source.url, "/tmp/foo.tar.gz");
+    }
+
+    #[test]
+    fn remote_update_is_throttled() {
+        use crate::model::{LocalPackageRepository, PackageRepository, RemotePackage};
+
+        let mut container = super::StateContainer::default();
+        let remote = RemotePackage {
+            update_period_seconds: 60,
+            ..crate::test_util::test_remote(
+                "foo",
+                PackageRepository::Local(LocalPackageRepository {
+                    path: "/tmp/foo.tar.gz".to_string(),
+                }),
+            )
+        };
+
+        assert!(container.is_remote_update_due(&remote));
+
+        let last_checked = &mut container.current_state.remote_last_checked;
+        last_checked.insert("foo".to_string(), Utc::now());
+        assert!(!container.is_remote_update_due(&remote));
+
+        let last_checked = &mut container.current_state.remote_last_checked;
+        last_checked.insert("foo".to_string(), Utc::now() - chrono::Duration::minutes(2));
+        assert!(container.is_remote_update_due(&remote));
     }
 
     #[cfg(test)]
@@ -715,13 +1137,19 @@ mod v1 {
             version: version.to_string(),
             achived_at: chrono::Utc::now(),
             file_hashes: Default::default(),
+            env: Default::default(),
+            entrypoint_options: Default::default(),
+            aliases: Default::default(),
+            target: None,
+            dependencies: Default::default(),
         };
 
         InstalledPackageContainer {
             path_to_root: "/tmp/fake".to_string(),
             package,
-            remote_name: None,
+            source: None,
             etag: None,
+            last_modified: None,
         }
     }
 }
@@ -0,0 +1,98 @@
+use crate::commands::{archive_package, ArchiveOptions};
+use crate::model::{PackageRepository, RemotePackage};
+use crate::remote::DownloadedArtifact;
+use crate::util::GlobalFolders;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A new temp dir, and global folders that keep everything inside of it.
+pub fn temp_global_folder() -> (PathBuf, GlobalFolders) {
+    let temp_dir = std::env::temp_dir().join(format!("toolup-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir).unwrap();
+    let temp_dir_path = temp_dir.display().to_string();
+    let global_folder = GlobalFolders::new(Some(temp_dir_path.clone()), Some(temp_dir_path));
+    (temp_dir, global_folder)
+}
+
+/// An archive that's already on disk, the way `toolup package install` passes it along.
+pub fn local_artifact(path: &Path) -> DownloadedArtifact {
+    DownloadedArtifact {
+        path: path.to_path_buf(),
+        etag: None,
+        last_modified: None,
+        source: None,
+        trusted_keys: Vec::new(),
+        install_limits: Default::default(),
+    }
+}
+
+/// Archive a package `foo`, with one entrypoint that contains `contents`.
+pub async fn make_test_archive(
+    temp_dir: &Path,
+    version: &str,
+    contents: &str,
+) -> DownloadedArtifact {
+    make_test_archive_with(temp_dir, version, contents, &Default::default()).await
+}
+
+pub async fn make_test_archive_with(
+    temp_dir: &Path,
+    version: &str,
+    contents: &str,
+    options: &ArchiveOptions,
+) -> DownloadedArtifact {
+    make_named_archive(temp_dir, "foo", version, contents, "", options).await
+}
+
+/// Archive a package with one entrypoint, named after the package. `extra_config` is added to the `package.toml`.
+pub async fn make_named_archive(
+    temp_dir: &Path,
+    name: &str,
+    version: &str,
+    contents: &str,
+    extra_config: &str,
+    options: &ArchiveOptions,
+) -> DownloadedArtifact {
+    let package_dir = temp_dir.join(format!("src-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&package_dir).unwrap();
+    fs::write(package_dir.join(name), contents).unwrap();
+    fs::write(
+        package_dir.join("package.toml"),
+        format!(
+            "name = \"{}\"\nversion = \"{}\"\nentrypoints = [\"{}\"]\n{}\n",
+            name, version, name, extra_config
+        ),
+    )
+    .unwrap();
+
+    let path = archive_package(
+        &package_dir.join("package.toml").display().to_string(),
+        &package_dir.display().to_string(),
+        &package_dir.display().to_string(),
+        options,
+    )
+    .await
+    .unwrap();
+
+    local_artifact(&path)
+}
+
+/// A remote that updates every time, without keys, a pinned sha256, or a retention policy.
+pub fn test_remote(name: &str, repository: PackageRepository) -> RemotePackage {
+    RemotePackage {
+        name: name.to_string(),
+        update_period_seconds: 0,
+        keep_versions: None,
+        trusted_keys: Vec::new(),
+        sha256: None,
+        install_limits: None,
+        repository,
+    }
+}
+
+/// Write the remote's config, the same as `toolup remote add` does.
+pub fn add_test_remote(global_folder: &GlobalFolders, remote: &RemotePackage) {
+    let config = global_folder.make_remote_tool_config(&remote.name);
+    fs::create_dir_all(config.parent().unwrap()).unwrap();
+    fs::write(config, serde_json::to_string(remote).unwrap()).unwrap();
+}
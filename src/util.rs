@@ -1,7 +1,9 @@
 use crate::cli::{GlobalConfig, LoggingOpts};
 use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::fs;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use tracing::debug;
@@ -13,6 +15,9 @@ pub const TOOL_DOWNLOAD_DIR: &str = "remote-download";
 pub const TOOLUP_GLOBAL_CONFIG_DIR: &str = "TOOLUP_GLOBAL_CONFIG_DIR";
 pub const TOOLUP_ROOT_TOOL_DIR: &str = "TOOLUP_ROOT_TOOL_DIR";
 pub const TOOL_LINK_FOLDER_NAME: &str = "bin";
+pub const TOOLUP_VERSION_OVERRIDE: &str = "TOOLUP_VERSION_OVERRIDE";
+pub const PROJECT_FILE_NAME: &str = ".toolup.toml";
+pub const TOOLUP_DISABLE_AUTO_UPDATE: &str = "TOOLUP_DISABLE_AUTO_UPDATE";
 
 #[derive(Debug)]
 pub struct GlobalFolders {
@@ -61,6 +66,12 @@ impl GlobalFolders {
         Path::new(&self.config_dir).join(TOOL_DOWNLOAD_DIR)
     }
 
+    /// Marker touched by `toolup-shim` when it starts a background update of a remote.
+    pub fn get_update_marker(&self, name: &str) -> PathBuf {
+        self.get_remote_download_dir()
+            .join(format!("{}.update-started", name))
+    }
+
     pub fn get_link_dir(&self) -> PathBuf {
         Path::new(&self.tool_root_dir).join(TOOL_LINK_FOLDER_NAME)
     }
@@ -115,12 +126,85 @@ impl LoggingOpts {
     }
 }
 
-pub fn get_hash_for_contents(input: impl AsRef<[u8]>) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(input);
-    let result = hasher.finalize();
+/// Hash a file without reading it all into memory.
+pub fn get_hash_for_file(path: &Path) -> Result<String, std::io::Error> {
+    let mut reader = HashingReader::new(fs::File::open(path)?);
+    std::io::copy(&mut reader, &mut std::io::sink())?;
+    Ok(reader.hash())
+}
+
+/// Wraps a reader, hashing the bytes as they are read.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
 
-    format!("{:x}", result)
+    /// The hex encoded sha256 of the bytes read so far.
+    pub fn hash(&self) -> String {
+        format!("{:x}", self.hasher.clone().finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Wraps a writer, hashing the bytes as they are written.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// The hex encoded sha256 of the bytes written so far.
+    pub fn hash(&self) -> String {
+        format!("{:x}", self.hasher.clone().finalize())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn validate_hashing_streams() {
+    let contents = "streamed contents".repeat(1024);
+    let expected = format!("{:x}", Sha256::digest(contents.as_bytes()));
+
+    let mut reader = HashingReader::new(contents.as_bytes());
+    std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+    assert_eq!(expected, reader.hash());
+
+    let mut writer = HashingWriter::new(Vec::new());
+    writer.write_all(contents.as_bytes()).unwrap();
+    assert_eq!(expected, writer.hash());
 }
 
 #[cfg(target_family = "unix")]
@@ -157,10 +241,16 @@ pub fn set_executable(path: &Path) {
 #[cfg(target_family = "windows")]
 pub fn set_executable(_path: &PathBuf) {}
 
+/// Replace the current process with `exe_path`, adding `env` to the environment it inherits.
 #[cfg(target_family = "unix")]
-pub fn exec(exe_path: String, args: Vec<String>) {
+pub fn exec(exe_path: String, args: Vec<String>, env: BTreeMap<String, String>) {
     use std::ffi::CString;
 
+    // execv passes on the current environment, so set the package's variables on this process.
+    for (key, value) in env {
+        std::env::set_var(key, value);
+    }
+
     // arg[0] needs to be the exec
     let mut nix_args = vec![exe_path.clone()];
     nix_args.extend(args);
@@ -174,18 +264,56 @@ pub fn exec(exe_path: String, args: Vec<String>) {
 }
 
 #[cfg(target_family = "windows")]
-pub fn exec(path: String, args: Vec<String>) {
+pub fn exec(path: String, args: Vec<String>, env: BTreeMap<String, String>) {
     use std::process::{self, Command};
     let status = Command::new("cmd")
         .arg("/C")
         .arg(path)
         .arg(args.join(" "))
+        .envs(env)
         .status()
         .unwrap();
 
     process::exit(status.code().unwrap_or(0));
 }
 
+/// Start a process that is not attached to the caller's session, and is not waited on.
+#[cfg(target_family = "unix")]
+pub fn spawn_detached(program: &Path, args: &[&str]) -> Result<(), std::io::Error> {
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    unsafe {
+        command.pre_exec(|| {
+            nix::unistd::setsid()?;
+            Ok(())
+        });
+    }
+
+    command.spawn()?;
+    Ok(())
+}
+
+#[cfg(target_family = "windows")]
+pub fn spawn_detached(program: &Path, args: &[&str]) -> Result<(), std::io::Error> {
+    use std::process::Stdio;
+
+    Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
 pub fn extract_env_from_script(
     script: &crate::model::AuthScript,
 ) -> Result<BTreeMap<String, String>, std::io::Error> {
@@ -221,6 +349,123 @@ fn validate_extract() {
     assert_eq!("foo", extracted.get("bar").unwrap());
 }
 
+/// Print rows to stdout, padding each column to the widest value in it.
+pub fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|x| x.len()).collect();
+    for row in &rows {
+        for (i, value) in row.iter().enumerate() {
+            widths[i] = widths[i].max(value.len());
+        }
+    }
+
+    let headers = headers.iter().map(|x| x.to_string()).collect();
+    for row in std::iter::once(headers).chain(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("{:width$}", value, width = widths[i]))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
 pub fn make_package_id(name: &str, version: &str) -> String {
     format!("urn:package:toolup/{}/{}", name, version)
 }
+
+/// `<timestamp>.<random>`, for temp paths that `toolup gc` removes by age. The random part keeps
+/// paths made in the same second apart.
+pub fn unique_timestamp(timestamp: i64) -> String {
+    let random = uuid::Uuid::new_v4().simple().to_string();
+    format!("{}.{}", timestamp, &random[..8])
+}
+
+/// Split a `<package>@<version>` spec into its parts. The version is optional.
+pub fn split_package_spec(spec: &str) -> (&str, Option<&str>) {
+    match spec.rsplit_once('@') {
+        Some((name, version)) => (name, Some(version)),
+        None => (spec, None),
+    }
+}
+
+#[test]
+fn validate_split_package_spec() {
+    assert_eq!(("foo", Some("1.2.3")), split_package_spec("foo@1.2.3"));
+    assert_eq!(
+        ("hello world", Some("1.0.0")),
+        split_package_spec("hello world@1.0.0")
+    );
+    assert_eq!(("foo", None), split_package_spec("foo"));
+}
+
+/// Parse a version range. A bare version, like `1.2`, only matches that version and its
+/// patches, where semver would otherwise treat it as `^1.2`.
+pub fn parse_version_req(spec: &str) -> Result<semver::VersionReq, semver::Error> {
+    if spec.starts_with(|c: char| c.is_ascii_digit()) {
+        semver::VersionReq::parse(&format!("={}", spec))
+    } else {
+        semver::VersionReq::parse(spec)
+    }
+}
+
+#[test]
+fn validate_parse_version_req() {
+    let version = semver::Version::parse("1.2.3").unwrap();
+    assert!(parse_version_req("1.2").unwrap().matches(&version));
+    assert!(!parse_version_req("1.3").unwrap().matches(&version));
+    assert!(parse_version_req("^1.0").unwrap().matches(&version));
+    assert!(!parse_version_req(">=2").unwrap().matches(&version));
+    assert!(parse_version_req("not a version").is_err());
+}
+
+/// Order versions by semver, falling back to lexical ordering for versions that aren't semver.
+/// Semver versions are always ordered above versions that aren't.
+pub fn compare_versions(left: &str, right: &str) -> Ordering {
+    match (semver::Version::parse(left), semver::Version::parse(right)) {
+        (Ok(left), Ok(right)) => left.cmp(&right),
+        (Ok(_), Err(_)) => Ordering::Greater,
+        (Err(_), Ok(_)) => Ordering::Less,
+        (Err(_), Err(_)) => left.cmp(right),
+    }
+}
+
+/// True when `version` is `spec`, or is semver and matches `spec` as a version range.
+pub fn version_matches(version: &str, spec: &str) -> bool {
+    if version == spec {
+        return true;
+    }
+
+    match (semver::Version::parse(version), parse_version_req(spec)) {
+        (Ok(version), Ok(version_req)) => version_req.matches(&version),
+        _ => false,
+    }
+}
+
+#[test]
+fn validate_compare_versions() {
+    let mut versions = vec![
+        "1.10.0",
+        "nightly",
+        "1.2.0",
+        "2.0.0-beta.1",
+        "beta",
+        "2.0.0",
+    ];
+    versions.sort_by(|a, b| compare_versions(a, b));
+    assert_eq!(
+        vec![
+            "beta",
+            "nightly",
+            "1.2.0",
+            "1.10.0",
+            "2.0.0-beta.1",
+            "2.0.0"
+        ],
+        versions
+    );
+
+    assert!(version_matches("nightly", "nightly"));
+    assert!(version_matches("1.2.3", "~1.2"));
+    assert!(!version_matches("2.0.0", "^1.2"));
+    assert!(!version_matches("nightly", "^1.2"));
+}
@@ -0,0 +1,3 @@
+#!/bin/bash
+
+echo "export TOOLUP_HTTP_TOKEN=secret"
\ No newline at end of file